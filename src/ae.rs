@@ -1,16 +1,66 @@
-use crate::{Chunk, SizeParams};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams, ValueWidth};
+
+use std::f64::consts::E;
 
 const MIN_CHUNK_SIZE: usize = 1024 * 8;
 const MAX_CHUNK_SIZE: usize = 1024 * 16;
 
+/// Kind of extremum that is searched for.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExtremumMode {
+    Max,
+    Min,
+}
+
+/// Parameters of AE: the kind of extremum and the width of compared values,
+/// which are single bytes by default. The window is derived from the sizes.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    mode: ExtremumMode,
+    value_width: ValueWidth,
+}
+
+impl Config {
+    pub fn new(mode: ExtremumMode) -> Self {
+        Self {
+            mode,
+            value_width: ValueWidth::U8,
+        }
+    }
+
+    /// Returns the config that compares values of the given width instead of single bytes.
+    pub fn with_value_width(self, value_width: ValueWidth) -> Self {
+        Self {
+            value_width,
+            ..self
+        }
+    }
+
+    pub fn mode(&self) -> ExtremumMode {
+        self.mode
+    }
+
+    pub fn value_width(&self) -> ValueWidth {
+        self.value_width
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(ExtremumMode::Max)
+    }
+}
+
 pub struct Chunker<'a> {
     buf: &'a [u8],
     len: usize,
     pos: usize,
     chunk_start: usize,
     sizes: SizeParams,
-    max_value: u8,
-    max_position: usize,
+    mode: ExtremumMode,
+    value_width: ValueWidth,
+    extreme_value: u64,
+    extreme_position: usize,
     window_size: usize,
 }
 impl<'a> Chunker<'a> {
@@ -22,17 +72,116 @@ impl<'a> Chunker<'a> {
         }
     }
 
+    /// Creates a new chunker that searches for maxima using the window derived from `sizes`.
     pub fn new(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_mode(buf, sizes, ExtremumMode::Max)
+    }
+
+    /// Creates a new chunker that searches for extrema of single bytes of the given kind
+    /// using the window derived from `sizes`.
+    pub fn with_mode(buf: &'a [u8], sizes: SizeParams, mode: ExtremumMode) -> Self {
+        Self::with_window(
+            buf,
+            sizes,
+            mode,
+            Self::window_size_for(sizes, ValueWidth::U8),
+            ValueWidth::U8,
+        )
+    }
+
+    pub fn with_window(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        mode: ExtremumMode,
+        window_size: usize,
+        value_width: ValueWidth,
+    ) -> Self {
         Chunker {
             buf,
             len: buf.len(),
             pos: 0,
             chunk_start: 0,
             sizes,
-            max_value: 0,
-            max_position: 0,
-            window_size: 32,
+            mode,
+            value_width,
+            extreme_value: 0,
+            extreme_position: 0,
+            window_size,
+        }
+    }
+
+    /// Calculates the window size that gives `avg` expected chunk size for random data.
+    ///
+    /// The AE paper assumes distinct values and gives `avg / (e - 1)`, which is used for values
+    /// of two bytes and more. Single bytes soon reach the largest value and stay there,
+    /// so their window is found from the expected chunk length of 256 possible values.
+    pub fn window_size_for(sizes: SizeParams, value_width: ValueWidth) -> usize {
+        if value_width != ValueWidth::U8 {
+            return ((sizes.avg as f64 / (E - 1.0)).round() as usize).max(1);
+        }
+
+        // expected length grows with the window and exceeds it
+        let (mut low, mut high) = (1, sizes.avg.max(1));
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if expected_byte_chunk_length(middle) < sizes.avg as f64 {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
         }
+        low
+    }
+
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Self::default_config())?;
+        Ok(Self::new(buf, sizes))
+    }
+
+    /// Creates a new chunker if `sizes` and `window_size` can be used by the algorithm.
+    pub fn try_with_window(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        mode: ExtremumMode,
+        window_size: usize,
+        value_width: ValueWidth,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Config::new(mode).with_value_width(value_width))?;
+        if window_size == 0 || window_size >= sizes.max {
+            return Err(ChunkerError::InvalidParameter {
+                name: "window_size",
+                reason: "has to be positive and less than max chunk size",
+            });
+        }
+        Ok(Self::with_window(
+            buf,
+            sizes,
+            mode,
+            window_size,
+            value_width,
+        ))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            len: buf.len(),
+            pos: 0,
+            chunk_start: 0,
+            sizes: self.sizes,
+            mode: self.mode,
+            value_width: self.value_width,
+            extreme_value: self.extreme_value,
+            extreme_position: self.extreme_position,
+            window_size: self.window_size,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + 2 * self.value_width.bytes()
     }
 
     fn find_border(&mut self) -> Option<usize> {
@@ -45,29 +194,66 @@ impl<'a> Chunker<'a> {
             return Some(self.pos);
         }
 
-        self.pos += 1;
-        self.max_value = self.buf[self.pos];
-        self.max_position = self.pos;
+        // values and borders stay on multiples of the width from the chunk start
+        let width = self.value_width.bytes();
+        self.pos += width;
+        self.extreme_value = self.value_width.read(self.buf, self.pos);
+        self.extreme_position = self.pos;
 
         while self.pos < self.len {
             if self.pos - self.chunk_start > self.sizes.max {
                 return Some(self.pos);
             }
 
-            if self.buf[self.pos] < self.max_value {
-                if self.pos == self.max_position + self.window_size {
-                    return Some(self.pos);
-                }
-            } else {
-                self.max_value = self.buf[self.pos];
-                self.max_position = self.pos;
+            let value = self.value_width.read(self.buf, self.pos);
+            if self.is_extreme(value) {
+                self.extreme_value = value;
+                self.extreme_position = self.pos;
+            } else if self.pos >= self.extreme_position + self.window_size {
+                return Some(self.pos);
             }
 
-            self.pos += 1;
+            self.pos += width;
         }
 
+        self.pos = self.len;
         Some(self.pos)
     }
+
+    /// Checks if `value` replaces the current extremum. Equal values do not.
+    fn is_extreme(&self, value: u64) -> bool {
+        match self.mode {
+            ExtremumMode::Max => value > self.extreme_value,
+            ExtremumMode::Min => value < self.extreme_value,
+        }
+    }
+}
+
+/// Expected chunk length for random bytes and `window_size`, not limited by max size.
+///
+/// The chunk starts with its first extremum and ends `window_size` bytes after the last one.
+/// After an extremum `m`, the next `window_size` bytes either do not exceed it, or the first byte
+/// that does becomes a uniformly distributed new extremum.
+fn expected_byte_chunk_length(window_size: usize) -> f64 {
+    const VALUES: usize = 256;
+    let window_size = window_size as f64;
+
+    // remaining length after each extremum, from the largest down
+    let mut remaining = [0.0; VALUES];
+    let mut sum_above = 0.0;
+    for extremum in (0..VALUES).rev() {
+        let above = VALUES - 1 - extremum;
+        let not_exceeding = (extremum + 1) as f64 / VALUES as f64;
+        remaining[extremum] = if above == 0 {
+            window_size
+        } else {
+            let exceeded = 1.0 - not_exceeding.powf(window_size);
+            exceeded / (1.0 - not_exceeding) + exceeded * sum_above / above as f64
+        };
+        sum_above += remaining[extremum];
+    }
+
+    1.0 + sum_above / VALUES as f64
 }
 
 impl Iterator for Chunker<'_> {
@@ -83,3 +269,109 @@ impl Iterator for Chunker<'_> {
         Some(Chunk::new(start, length))
     }
 }
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_window(
+            buf,
+            sizes,
+            config.mode(),
+            Chunker::window_size_for(sizes, config.value_width()),
+            config.value_width(),
+        )
+    }
+
+    fn validate(sizes: SizeParams, _config: &Self::Config) -> Result<(), ChunkerError> {
+        // the byte after the chunk start is read before the search begins
+        sizes.check_with_min(2)
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ae::{Chunker, Config, ExtremumMode};
+    use crate::{generate_data, Chunking, SizeParams, ValueWidth};
+
+    fn generate_dna(size: usize) -> Vec<u8> {
+        (0..size)
+            .map(|_| b"ACGT"[rand::random::<usize>() % 4])
+            .collect()
+    }
+
+    #[test]
+    fn average_size_follows_sizes_in_both_modes() {
+        let data = generate_data(4 * 1024 * 1024);
+
+        for sizes in [
+            SizeParams::new(2, 256, 65536),
+            SizeParams::new(2048, 4096, 8192),
+            SizeParams::new(4096, 8192, 16384),
+            Chunker::default_sizes(),
+        ] {
+            for mode in [ExtremumMode::Max, ExtremumMode::Min] {
+                let chunks = Chunker::with_mode(&data, sizes, mode).collect::<Vec<_>>();
+                let average = data.len() / chunks.len();
+                assert!(
+                    average.abs_diff(sizes.avg) < sizes.avg / 10,
+                    "{} {:?} {}",
+                    sizes,
+                    mode,
+                    average
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn min_mode_finds_minima() {
+        let data = generate_data(64 * 1024);
+        let sizes = SizeParams::new(64, 1024, 8192);
+        let window_size = 100;
+
+        for chunk in
+            Chunker::with_window(&data, sizes, ExtremumMode::Min, window_size, ValueWidth::U8)
+        {
+            let end = chunk.pos + chunk.len;
+            if chunk.len > sizes.max || end == data.len() {
+                continue;
+            }
+
+            // the byte before the window is the first minimum of the chunk after its first byte,
+            // and the window ends with the border byte
+            let minimum = end - window_size;
+            assert!(data[chunk.pos + 1..minimum]
+                .iter()
+                .all(|&byte| byte > data[minimum]));
+            assert!(data[minimum..=end]
+                .iter()
+                .all(|&byte| byte >= data[minimum]));
+        }
+    }
+
+    #[test]
+    fn wide_values_keep_average_size_on_low_entropy_data() {
+        let data = generate_dna(2 * 1024 * 1024);
+        let sizes = SizeParams::new(2, 4096, 65536);
+        let average = |value_width| {
+            let config = Config::default().with_value_width(value_width);
+            data.len() / Chunker::with_config(&data, sizes, config).count()
+        };
+
+        let average = average(ValueWidth::U64);
+        assert!(average.abs_diff(sizes.avg) < sizes.avg / 10, "{}", average);
+    }
+}
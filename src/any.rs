@@ -0,0 +1,492 @@
+use crate::{
+    ae, buzhash, fastcdc, fixed, gear, leap_based, maxp, rabin, ram, seq, supercdc, tttd, ultra,
+    Chunk, ChunkerError, Chunking, SizeParams,
+};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Algorithms that can be selected at runtime.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Algorithm {
+    Ae,
+    Buzhash,
+    FastCdc,
+    Fixed,
+    Gear,
+    Leap,
+    Maxp,
+    Rabin,
+    Ram,
+    Seq,
+    Super,
+    Tttd,
+    Ultra,
+}
+
+/// Chunker with an algorithm chosen at runtime.
+///
+/// Every variant wraps the chunker of the corresponding module,
+/// so custom-configured chunkers can be converted into `AnyChunker` using `From`.
+pub enum AnyChunker<'a> {
+    Ae(ae::Chunker<'a>),
+    Buzhash(buzhash::Chunker<'a>),
+    FastCdc(fastcdc::Chunker<'a>),
+    Fixed(fixed::Chunker<'a>),
+    Gear(gear::Chunker<'a>),
+    Leap(leap_based::Chunker<'a>),
+    Maxp(maxp::Chunker<'a>),
+    Rabin(rabin::Chunker<'a>),
+    Ram(ram::Chunker<'a>),
+    Seq(seq::Chunker<'a>),
+    Super(supercdc::Chunker<'a>),
+    Tttd(tttd::Chunker<'a>),
+    Ultra(ultra::Chunker<'a>),
+}
+
+macro_rules! dispatch {
+    ($value:expr, $chunker:ident => $body:expr) => {
+        match $value {
+            AnyChunker::Ae($chunker) => $body,
+            AnyChunker::Buzhash($chunker) => $body,
+            AnyChunker::FastCdc($chunker) => $body,
+            AnyChunker::Fixed($chunker) => $body,
+            AnyChunker::Gear($chunker) => $body,
+            AnyChunker::Leap($chunker) => $body,
+            AnyChunker::Maxp($chunker) => $body,
+            AnyChunker::Rabin($chunker) => $body,
+            AnyChunker::Ram($chunker) => $body,
+            AnyChunker::Seq($chunker) => $body,
+            AnyChunker::Super($chunker) => $body,
+            AnyChunker::Tttd($chunker) => $body,
+            AnyChunker::Ultra($chunker) => $body,
+        }
+    };
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 13] = [
+        Algorithm::Ae,
+        Algorithm::Buzhash,
+        Algorithm::FastCdc,
+        Algorithm::Fixed,
+        Algorithm::Gear,
+        Algorithm::Leap,
+        Algorithm::Maxp,
+        Algorithm::Rabin,
+        Algorithm::Ram,
+        Algorithm::Seq,
+        Algorithm::Super,
+        Algorithm::Tttd,
+        Algorithm::Ultra,
+    ];
+
+    /// Returns default chunk sizes of the algorithm.
+    pub fn default_sizes(&self) -> SizeParams {
+        match self {
+            Algorithm::Ae => ae::Chunker::default_sizes(),
+            Algorithm::Buzhash => buzhash::Chunker::default_sizes(),
+            Algorithm::FastCdc => fastcdc::Chunker::default_sizes(),
+            Algorithm::Fixed => fixed::Chunker::default_sizes(),
+            Algorithm::Gear => gear::Chunker::default_sizes(),
+            Algorithm::Leap => leap_based::Chunker::default_sizes(),
+            Algorithm::Maxp => maxp::Chunker::default_sizes(),
+            Algorithm::Rabin => rabin::Chunker::default_sizes(),
+            Algorithm::Ram => ram::Chunker::default_sizes(),
+            Algorithm::Seq => seq::Chunker::default_sizes(),
+            Algorithm::Super => supercdc::Chunker::default_sizes(),
+            Algorithm::Tttd => tttd::Chunker::default_sizes(),
+            Algorithm::Ultra => ultra::Chunker::default_sizes(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Ae => "ae",
+            Algorithm::Buzhash => "buzhash",
+            Algorithm::FastCdc => "fastcdc",
+            Algorithm::Fixed => "fixed",
+            Algorithm::Gear => "gear",
+            Algorithm::Leap => "leap",
+            Algorithm::Maxp => "maxp",
+            Algorithm::Rabin => "rabin",
+            Algorithm::Ram => "ram",
+            Algorithm::Seq => "seq",
+            Algorithm::Super => "super",
+            Algorithm::Tttd => "tttd",
+            Algorithm::Ultra => "ultra",
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Algorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown algorithm: {}", s))
+    }
+}
+
+impl<'a> AnyChunker<'a> {
+    /// Creates a chunker of the given algorithm with default algorithm-specific parameters.
+    pub fn new(algorithm: Algorithm, buf: &'a [u8], sizes: SizeParams) -> Self {
+        match algorithm {
+            Algorithm::Ae => ae::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Buzhash => buzhash::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::FastCdc => fastcdc::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Fixed => fixed::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Gear => gear::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Leap => leap_based::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Maxp => maxp::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Rabin => rabin::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Ram => ram::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Seq => seq::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Super => supercdc::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Tttd => tttd::Chunker::with_sizes(buf, sizes).into(),
+            Algorithm::Ultra => ultra::Chunker::with_sizes(buf, sizes).into(),
+        }
+    }
+
+    /// Creates a chunker of the given algorithm with default algorithm-specific parameters
+    /// if `sizes` can be used by the algorithm.
+    pub fn try_new(
+        algorithm: Algorithm,
+        buf: &'a [u8],
+        sizes: SizeParams,
+    ) -> Result<Self, ChunkerError> {
+        match algorithm {
+            Algorithm::Ae => try_with_sizes::<ae::Chunker>(buf, sizes),
+            Algorithm::Buzhash => try_with_sizes::<buzhash::Chunker>(buf, sizes),
+            Algorithm::FastCdc => try_with_sizes::<fastcdc::Chunker>(buf, sizes),
+            Algorithm::Fixed => try_with_sizes::<fixed::Chunker>(buf, sizes),
+            Algorithm::Gear => try_with_sizes::<gear::Chunker>(buf, sizes),
+            Algorithm::Leap => try_with_sizes::<leap_based::Chunker>(buf, sizes),
+            Algorithm::Maxp => try_with_sizes::<maxp::Chunker>(buf, sizes),
+            Algorithm::Rabin => try_with_sizes::<rabin::Chunker>(buf, sizes),
+            Algorithm::Ram => try_with_sizes::<ram::Chunker>(buf, sizes),
+            Algorithm::Seq => try_with_sizes::<seq::Chunker>(buf, sizes),
+            Algorithm::Super => try_with_sizes::<supercdc::Chunker>(buf, sizes),
+            Algorithm::Tttd => try_with_sizes::<tttd::Chunker>(buf, sizes),
+            Algorithm::Ultra => try_with_sizes::<ultra::Chunker>(buf, sizes),
+        }
+    }
+
+    /// Creates a chunker of the given algorithm with its default sizes and parameters.
+    pub fn with_default_sizes(algorithm: Algorithm, buf: &'a [u8]) -> Self {
+        Self::new(algorithm, buf, algorithm.default_sizes())
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            AnyChunker::Ae(_) => Algorithm::Ae,
+            AnyChunker::Buzhash(_) => Algorithm::Buzhash,
+            AnyChunker::FastCdc(_) => Algorithm::FastCdc,
+            AnyChunker::Fixed(_) => Algorithm::Fixed,
+            AnyChunker::Gear(_) => Algorithm::Gear,
+            AnyChunker::Leap(_) => Algorithm::Leap,
+            AnyChunker::Maxp(_) => Algorithm::Maxp,
+            AnyChunker::Rabin(_) => Algorithm::Rabin,
+            AnyChunker::Ram(_) => Algorithm::Ram,
+            AnyChunker::Seq(_) => Algorithm::Seq,
+            AnyChunker::Super(_) => Algorithm::Super,
+            AnyChunker::Tttd(_) => Algorithm::Tttd,
+            AnyChunker::Ultra(_) => Algorithm::Ultra,
+        }
+    }
+
+    pub fn sizes(&self) -> SizeParams {
+        dispatch!(self, chunker => chunker.sizes())
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> AnyChunker<'_> {
+        dispatch!(self, chunker => chunker.rebase(buf).into())
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        dispatch!(self, chunker => chunker.lookahead())
+    }
+}
+
+fn try_with_sizes<'a, C>(buf: &'a [u8], sizes: SizeParams) -> Result<AnyChunker<'a>, ChunkerError>
+where
+    C: Chunking<'a> + Into<AnyChunker<'a>>,
+{
+    C::try_with_config(buf, sizes, C::default_config()).map(Into::into)
+}
+
+impl AnyChunker<'static> {
+    /// Finds the length of the next chunk in `buf`, which has to start at the beginning of the chunk.
+    ///
+    /// Returns the chunker detached from `buf` along with the length.
+    pub(crate) fn find_in(self, buf: &[u8]) -> (Self, Option<usize>) {
+        let mut chunker = self.rebase(buf);
+        let length = chunker.next().map(|chunk| chunk.len);
+        (chunker.rebase(&[]), length)
+    }
+}
+
+impl Iterator for AnyChunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        dispatch!(self, chunker => chunker.next())
+    }
+}
+
+impl<'a> From<ae::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: ae::Chunker<'a>) -> Self {
+        AnyChunker::Ae(chunker)
+    }
+}
+
+impl<'a> From<buzhash::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: buzhash::Chunker<'a>) -> Self {
+        AnyChunker::Buzhash(chunker)
+    }
+}
+
+impl<'a> From<fastcdc::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: fastcdc::Chunker<'a>) -> Self {
+        AnyChunker::FastCdc(chunker)
+    }
+}
+
+impl<'a> From<fixed::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: fixed::Chunker<'a>) -> Self {
+        AnyChunker::Fixed(chunker)
+    }
+}
+
+impl<'a> From<gear::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: gear::Chunker<'a>) -> Self {
+        AnyChunker::Gear(chunker)
+    }
+}
+
+impl<'a> From<leap_based::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: leap_based::Chunker<'a>) -> Self {
+        AnyChunker::Leap(chunker)
+    }
+}
+
+impl<'a> From<maxp::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: maxp::Chunker<'a>) -> Self {
+        AnyChunker::Maxp(chunker)
+    }
+}
+
+impl<'a> From<rabin::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: rabin::Chunker<'a>) -> Self {
+        AnyChunker::Rabin(chunker)
+    }
+}
+
+impl<'a> From<ram::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: ram::Chunker<'a>) -> Self {
+        AnyChunker::Ram(chunker)
+    }
+}
+
+impl<'a> From<seq::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: seq::Chunker<'a>) -> Self {
+        AnyChunker::Seq(chunker)
+    }
+}
+
+impl<'a> From<supercdc::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: supercdc::Chunker<'a>) -> Self {
+        AnyChunker::Super(chunker)
+    }
+}
+
+impl<'a> From<tttd::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: tttd::Chunker<'a>) -> Self {
+        AnyChunker::Tttd(chunker)
+    }
+}
+
+impl<'a> From<ultra::Chunker<'a>> for AnyChunker<'a> {
+    fn from(chunker: ultra::Chunker<'a>) -> Self {
+        AnyChunker::Ultra(chunker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fastcdc::{Normalization, Version};
+    use crate::rabin::ChunkerParams;
+    use crate::{
+        ae, buzhash, fastcdc, gear, generate_data, leap_based, maxp, rabin, ram, seq, supercdc,
+        tttd, ultra, Algorithm, AnyChunker, Chunk, ChunkerError, Chunking, SizeParams,
+    };
+    use std::collections::HashMap;
+
+    fn chunk_generic<'a, C: Chunking<'a>>(buf: &'a [u8], sizes: SizeParams) -> Vec<Chunk> {
+        C::with_sizes(buf, sizes).collect()
+    }
+
+    fn assert_key_changes_chunks<'a, C: Chunking<'a>>(
+        buf: &'a [u8],
+        keyed: impl Fn([u8; 32]) -> C::Config,
+    ) {
+        assert_key_changes_chunks_with_sizes::<C>(buf, C::default_sizes(), keyed);
+    }
+
+    fn assert_key_changes_chunks_with_sizes<'a, C: Chunking<'a>>(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        keyed: impl Fn([u8; 32]) -> C::Config,
+    ) {
+        let chunks = |config| C::with_config(buf, sizes, config).collect::<Vec<_>>();
+        let (first, second) = ([1; 32], [2; 32]);
+
+        let unkeyed = chunks(C::default_config());
+        assert_eq!(unkeyed, chunk_generic::<C>(buf, sizes));
+        assert_covers(&chunks(keyed(first)), buf.len());
+        assert_eq!(chunks(keyed(first)), chunks(keyed(first)));
+        assert_ne!(chunks(keyed(first)), unkeyed);
+        assert_ne!(chunks(keyed(first)), chunks(keyed(second)));
+    }
+
+    fn assert_covers(chunks: &[Chunk], len: usize) {
+        let mut expected_pos = 0;
+        for chunk in chunks {
+            assert_eq!(chunk.pos, expected_pos);
+            expected_pos += chunk.len;
+        }
+        assert_eq!(expected_pos, len);
+    }
+
+    #[test]
+    fn any_chunker_covers_data_for_all_algorithms() {
+        let data = generate_data(1024 * 1024);
+
+        for algorithm in Algorithm::ALL {
+            let chunks = AnyChunker::with_default_sizes(algorithm, &data).collect::<Vec<_>>();
+            assert_covers(&chunks, data.len());
+        }
+    }
+
+    #[test]
+    fn any_chunker_matches_generic_path() {
+        let data = generate_data(1024 * 1024);
+
+        let generic = chunk_generic::<seq::Chunker>(&data, SizeParams::seq_default());
+        let any = AnyChunker::new(Algorithm::Seq, &data, SizeParams::seq_default());
+        assert_eq!(generic, any.collect::<Vec<_>>());
+
+        let generic = chunk_generic::<supercdc::Chunker>(&data, SizeParams::super_default());
+        let any = AnyChunker::from(supercdc::Chunker::new(&data));
+        assert_eq!(generic, any.collect::<Vec<_>>());
+
+        let generic = chunk_generic::<ae::Chunker>(&data, ae::Chunker::default_sizes());
+        assert_covers(&generic, data.len());
+    }
+
+    #[test]
+    fn keys_change_chunks_of_table_driven_algorithms() {
+        let data = generate_data(1024 * 1024);
+
+        // borg's default chunks are larger than the data
+        assert_key_changes_chunks_with_sizes::<buzhash::Chunker>(
+            &data,
+            SizeParams::gear_default(),
+            |key| buzhash::Config::keyed(64, &key),
+        );
+        assert_key_changes_chunks::<fastcdc::Chunker>(&data, |key| {
+            fastcdc::Config::new(Version::V2020, Normalization::Level1).with_key(key)
+        });
+        assert_key_changes_chunks::<gear::Chunker>(&data, |key| {
+            gear::Config::default().with_key(key)
+        });
+        assert_key_changes_chunks::<leap_based::Chunker>(&data, |key| {
+            leap_based::Config::default().with_key(key)
+        });
+        assert_key_changes_chunks::<maxp::Chunker>(&data, |key| {
+            maxp::Config::new(4096).with_key(key)
+        });
+        assert_key_changes_chunks::<rabin::Chunker>(&data, |key| ChunkerParams::keyed(&key));
+        assert_key_changes_chunks::<supercdc::Chunker>(&data, |key| {
+            supercdc::Config::new(HashMap::new(), Normalization::Level1).with_key(key)
+        });
+        assert_key_changes_chunks::<tttd::Chunker>(&data, |key| ChunkerParams::keyed(&key));
+        assert_key_changes_chunks::<ultra::Chunker>(&data, |key| {
+            ultra::Config::default().with_key(key)
+        });
+    }
+
+    #[test]
+    fn algorithm_is_parsed_from_name() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(algorithm.to_string().parse::<Algorithm>(), Ok(algorithm));
+        }
+        assert_eq!("ULTRA".parse::<Algorithm>(), Ok(Algorithm::Ultra));
+        assert!("fixedsize".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        assert!(SizeParams::try_new(4096, 2048, 8192).is_err());
+
+        for algorithm in Algorithm::ALL {
+            assert!(AnyChunker::try_new(algorithm, &[], algorithm.default_sizes()).is_ok());
+        }
+
+        // these start hashing from the chunk start if min is small or do not use min at all,
+        // so they have no lower bound
+        let unbounded = [
+            Algorithm::Buzhash,
+            Algorithm::Fixed,
+            Algorithm::Gear,
+            Algorithm::Maxp,
+            Algorithm::Rabin,
+            Algorithm::Tttd,
+        ];
+        for algorithm in unbounded {
+            assert!(AnyChunker::try_new(algorithm, &[], SizeParams::new(0, 4096, 8192)).is_ok());
+        }
+        for algorithm in Algorithm::ALL
+            .into_iter()
+            .filter(|a| !unbounded.contains(a))
+        {
+            assert!(matches!(
+                AnyChunker::try_new(algorithm, &[], SizeParams::new(0, 4096, 8192)),
+                Err(ChunkerError::MinTooSmall { min: 0, .. })
+            ));
+        }
+
+        let unordered = SizeParams {
+            min: 8192,
+            avg: 4096,
+            max: 16384,
+        };
+        assert!(matches!(
+            AnyChunker::try_new(Algorithm::Rabin, &[], unordered),
+            Err(ChunkerError::InvalidSizes { .. })
+        ));
+
+        let sizes = SizeParams::new(4096, 6000, 16384);
+        assert!(AnyChunker::try_new(Algorithm::Leap, &[], SizeParams::new(200, 300, 400)).is_err());
+        assert!(ram::Chunker::try_new(&[], sizes, 0).is_err());
+
+        let config = seq::Config::new(0, 55, 256);
+        let error = seq::Chunker::try_new(&[], sizes, config);
+        assert!(matches!(
+            error,
+            Err(ChunkerError::InvalidParameter {
+                name: "sequence_length",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use crate::stream::{OwnedChunk, StreamBuffer};
+use crate::{Algorithm, AnyChunker, SizeParams};
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Chunker that reads data from any `AsyncRead` source and gives out chunks as a `Stream`.
+///
+/// Uses the same buffering as `stream::Chunker` and produces the same chunks
+/// as the slice-based chunker of the same algorithm.
+/// Readers implementing `tokio::io::AsyncRead` can be used through the `tokio-util` compat layer.
+pub struct Chunker<R> {
+    reader: R,
+    buf: StreamBuffer,
+}
+
+impl<R: AsyncRead + Unpin> Chunker<R> {
+    /// Creates a chunker of the given algorithm with default algorithm-specific parameters.
+    pub fn new(reader: R, algorithm: Algorithm, sizes: SizeParams) -> Self {
+        Self::with_chunker(reader, AnyChunker::new(algorithm, &[], sizes))
+    }
+
+    /// Creates a chunker that uses the algorithm and parameters of `chunker`.
+    ///
+    /// `chunker` is expected to be created over an empty buffer, e.g.
+    /// `ultra::Chunker::new(&[], sizes).into()`.
+    pub fn with_chunker(reader: R, chunker: AnyChunker<'static>) -> Self {
+        Self {
+            reader,
+            buf: StreamBuffer::new(chunker),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for Chunker<R> {
+    type Item = io::Result<OwnedChunk>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while this.buf.needs_data() {
+            match Pin::new(&mut this.reader).poll_read(cx, this.buf.spare()) {
+                Poll::Ready(Ok(read)) => this.buf.advance(read),
+                Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(this.buf.next_chunk().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_stream::Chunker;
+    use crate::{generate_data, Algorithm, AnyChunker};
+    use futures::executor::block_on;
+    use futures::io::AsyncRead;
+    use futures::TryStreamExt;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Reader that is not ready on every other poll and gives out at most `step` bytes at a time.
+    struct PendingReader<'a> {
+        data: &'a [u8],
+        step: usize,
+        ready: bool,
+    }
+
+    impl AsyncRead for PendingReader<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let length = self.step.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Poll::Ready(Ok(length))
+        }
+    }
+
+    #[test]
+    fn async_stream_matches_slice_chunkers() {
+        let data = generate_data(1024 * 1024);
+
+        for algorithm in Algorithm::ALL {
+            let sizes = algorithm.default_sizes();
+            let reader = PendingReader {
+                data: &data,
+                step: 5000,
+                ready: false,
+            };
+
+            let stream_chunks =
+                block_on(Chunker::new(reader, algorithm, sizes).try_collect::<Vec<_>>()).unwrap();
+            let chunks = AnyChunker::new(algorithm, &data, sizes).collect::<Vec<_>>();
+
+            assert_eq!(stream_chunks.len(), chunks.len());
+            for (owned, chunk) in stream_chunks.iter().zip(chunks) {
+                assert_eq!(owned.pos, chunk.pos);
+                assert_eq!(owned.data, &data[chunk.pos..chunk.pos + chunk.len]);
+            }
+        }
+    }
+}
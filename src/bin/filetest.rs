@@ -1,7 +1,7 @@
 use cdc_chunkers::{
-    ae, leap_based, rabin, ram,
-    seq::{self, Config, OperationMode},
-    supercdc, ultra, Chunk, SizeParams,
+    ae, buzhash, fastcdc, fixed, gear, leap_based, maxp, rabin, ram,
+    seq::{self, Config},
+    supercdc, tttd, ultra, Chunk, SizeParams,
 };
 use clap::Parser;
 use sha3::{Digest, Sha3_256};
@@ -32,7 +32,6 @@ fn main() {
         Algorithm::Seq => chunk_file(seq::Chunker::new(
             &buf,
             SizeParams::seq_default(),
-            OperationMode::Increasing,
             Config::default(),
         )),
         Algorithm::AE => chunk_file(ae::Chunker::new(&buf, SizeParams::new(4096, 8092, 16384))),
@@ -41,6 +40,25 @@ fn main() {
             SizeParams::new(4096, 8192, 16384),
             32,
         )),
+        Algorithm::FastCDC => chunk_file(fastcdc::Chunker::new(
+            &buf,
+            SizeParams::fastcdc_default(),
+            fastcdc::Version::V2020,
+            fastcdc::Normalization::Level1,
+        )),
+        Algorithm::Gear => chunk_file(gear::Chunker::new(&buf, SizeParams::gear_default())),
+        Algorithm::Buzhash => chunk_file(buzhash::Chunker::new(
+            &buf,
+            SizeParams::buzhash_default(),
+            buzhash::Config::default(),
+        )),
+        Algorithm::TTTD => chunk_file(tttd::Chunker::new(
+            &buf,
+            rabin::ChunkerParams::new(),
+            SizeParams::tttd_default(),
+        )),
+        Algorithm::Fixed => chunk_file(fixed::Chunker::new(&buf, SizeParams::fixed_default())),
+        Algorithm::MAXP => chunk_file(maxp::Chunker::new(&buf, SizeParams::maxp_default(), 4096)),
     };
 
     check_chunks_length(&chunks, buf.len());
@@ -174,4 +192,10 @@ pub enum Algorithm {
     Seq,
     AE,
     RAM,
+    FastCDC,
+    Gear,
+    Buzhash,
+    TTTD,
+    Fixed,
+    MAXP,
 }
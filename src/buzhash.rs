@@ -0,0 +1,497 @@
+use crate::rng::KeyStream;
+use crate::{cut_mask, Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+
+const MIN_CHUNK_SIZE: usize = 1024 * 512;
+const AVG_CHUNK_SIZE: usize = 1024 * 512 + 1024 * 1024 * 2;
+const MAX_CHUNK_SIZE: usize = 1024 * 1024 * 8;
+
+const DEFAULT_WINDOW_SIZE: usize = 4095;
+
+/// Window size and table of the cyclic polynomial (buzhash) fingerprint.
+///
+/// `Config::new` uses borg's table with every entry XORed with a 32-bit seed,
+/// so that together with sizes from `borg_sizes` chunks are the same as borg's.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    window_size: usize,
+    table: Box<[u32; 256]>,
+}
+
+impl Config {
+    /// Creates a config using borg's table with every entry XORed with `seed`,
+    /// which is borg's `chunk_seed` as an unsigned 32-bit value.
+    pub fn new(window_size: usize, seed: u32) -> Self {
+        Self::with_table(window_size, &BORG_TABLE, seed)
+    }
+
+    /// Creates a config of borg's default chunker params with `seed`.
+    pub fn borg(seed: u32) -> Self {
+        Self::new(DEFAULT_WINDOW_SIZE, seed)
+    }
+
+    /// Creates a config using a table derived from a secret `key`,
+    /// so that borders can not be predicted without the key.
+    ///
+    /// Unlike a 32-bit seed, which only flips the same bits of every entry of borg's table,
+    /// the key changes every entry independently. Chunks are not compatible with borg.
+    pub fn keyed(window_size: usize, key: &[u8; 32]) -> Self {
+        let mut stream = KeyStream::new(b"buzhash", key);
+        let table = [0; 256].map(|_: u32| stream.next_u64() as u32);
+        Self::with_table(window_size, &table, 0)
+    }
+
+    /// Creates a config using `table` with every entry XORed with `seed`.
+    pub fn with_table(window_size: usize, table: &[u32; 256], seed: u32) -> Self {
+        let mut table = Box::new(*table);
+        for entry in table.iter_mut() {
+            *entry ^= seed;
+        }
+
+        Self { window_size, table }
+    }
+
+    /// Parses chunker params written in borg's format, e.g. `buzhash,19,23,21,4095`, which consist of
+    /// binary logarithms of min and max chunk sizes, the amount of mask bits and the window size.
+    ///
+    /// The `buzhash` prefix can be omitted, as in older borg versions.
+    pub fn from_borg_params(params: &str, seed: u32) -> Result<(SizeParams, Self), ChunkerError> {
+        let params = params.strip_prefix("buzhash,").unwrap_or(params);
+        let values = params
+            .split(',')
+            .map(|value| value.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ChunkerError::InvalidParameter {
+                name: "chunker_params",
+                reason: "has to be a list of non-negative integers",
+            })?;
+
+        let [min_exp, max_exp, mask_bits, window_size] = values[..] else {
+            return Err(ChunkerError::InvalidParameter {
+                name: "chunker_params",
+                reason: "has to contain min and max exponents, mask bits and window size",
+            });
+        };
+
+        let sizes = borg_sizes(min_exp, max_exp, mask_bits)?;
+        let config = Self::new(window_size, seed);
+        Chunker::validate(sizes, &config)?;
+        Ok((sizes, config))
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::borg(0)
+    }
+}
+
+/// Creates sizes for chunks between `2^min_exp` and `2^max_exp` bytes,
+/// which are cut where the lowest `mask_bits` bits of the fingerprint are zero.
+pub fn borg_sizes(
+    min_exp: usize,
+    max_exp: usize,
+    mask_bits: usize,
+) -> Result<SizeParams, ChunkerError> {
+    let power = |exp: usize, name| {
+        if exp < usize::BITS as usize {
+            Ok(1usize << exp)
+        } else {
+            Err(ChunkerError::InvalidParameter {
+                name,
+                reason: "is too large",
+            })
+        }
+    };
+
+    let min = power(min_exp, "min_exp")?;
+    let max = power(max_exp, "max_exp")?;
+    let avg = power(mask_bits, "mask_bits")?.saturating_add(min);
+
+    SizeParams::try_new(min, avg, max)
+}
+
+/// Chunker that cuts where the lowest bits of the buzhash fingerprint of the window
+/// starting at the border are zero, the same way as borg.
+/// The first tested window starts at min size, and the window has to be followed
+/// by at least one byte before max size or the end of data, otherwise the chunk ends there.
+///
+/// The mask gives `avg - min` expected cut length after min size, rounded to the nearest power of two.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    sizes: SizeParams,
+    config: Config,
+    mask: u32,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: MIN_CHUNK_SIZE,
+            avg: AVG_CHUNK_SIZE,
+            max: MAX_CHUNK_SIZE,
+        }
+    }
+
+    pub fn new(buf: &'a [u8], sizes: SizeParams, config: Config) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            sizes,
+            mask: cut_mask(sizes).min(u64::from(u32::MAX)) as u32,
+            config,
+        }
+    }
+
+    /// Creates a new chunker if the parameters can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams, config: Config) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &config)?;
+        Ok(Self::new(buf, sizes, config))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            pos: 0,
+            sizes: self.sizes,
+            config: self.config,
+            mask: self.mask,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max
+    }
+
+    fn find_border(&self, buf: &[u8]) -> usize {
+        let window_size = self.config.window_size;
+        if buf.len() <= self.sizes.min + window_size {
+            return buf.len();
+        }
+
+        let end = min(self.sizes.max, buf.len());
+        let table = &self.config.table;
+
+        let mut start = self.sizes.min;
+        let mut fingerprint = fingerprint(&buf[start..start + window_size], table);
+
+        let out_shift = (window_size % 32) as u32;
+        while fingerprint & self.mask != 0 && start + window_size < end {
+            fingerprint = fingerprint.rotate_left(1)
+                ^ table[buf[start] as usize].rotate_left(out_shift)
+                ^ table[buf[start + window_size] as usize];
+            start += 1;
+        }
+
+        if start + window_size < end {
+            start
+        } else {
+            end
+        }
+    }
+}
+
+/// Calculates the fingerprint of `window` from scratch.
+fn fingerprint(window: &[u8], table: &[u32; 256]) -> u32 {
+    window.iter().fold(0, |fingerprint, &byte| {
+        fingerprint.rotate_left(1) ^ table[byte as usize]
+    })
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = self.find_border(&self.buf[self.pos..]);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::new(buf, sizes, config)
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        if config.window_size == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "window_size",
+                reason: "has to be positive",
+            });
+        }
+
+        sizes.check_with_min(0)?;
+
+        // first window starts at min size and is followed by at least one byte
+        if sizes.min + config.window_size >= sizes.max {
+            return Err(ChunkerError::InvalidSizes {
+                sizes,
+                reason: "max has to be greater than min + window size",
+            });
+        }
+
+        if cut_mask(sizes) > u64::from(u32::MAX) {
+            return Err(ChunkerError::InvalidSizes {
+                sizes,
+                reason: "avg - min does not fit into 32-bit fingerprints",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+/// Table of borg's buzhash, `table_base` in its `_chunker.c`.
+#[rustfmt::skip]
+const BORG_TABLE: [u32; 256] = [
+    0xe7f831ec, 0xf4026465, 0xafb50cae, 0x6d553c7a, 0xd639efe3, 0x19a7b895, 0x9aba5b21, 0x5417d6d4,
+    0x35fd2b84, 0xd1f6a159, 0x3f8e323f, 0xb419551c, 0xf444cebf, 0x21dc3b80, 0xde8d1e36, 0x84a32436,
+    0xbeb35a9d, 0xa36f24aa, 0xa4e60186, 0x98d18ffe, 0x3f042f9e, 0xdb228bcd, 0x096474b7, 0x5c20c2f7,
+    0xf9eec872, 0xe8625275, 0xb9d38f80, 0xd48eb716, 0x22a950b4, 0x3cbaaeaa, 0xc37cddd3, 0x8fea6f6a,
+    0x1d55d526, 0x7fd6d3b3, 0xdaa072ee, 0x4345ac40, 0xa077c642, 0x8f2bd45b, 0x28509110, 0x55557613,
+    0xffc17311, 0xd961ffef, 0xe532c287, 0xaab95937, 0x46d38365, 0xb065c703, 0xf2d91d0f, 0x92cd4bb0,
+    0x4007c712, 0xf35509dd, 0x505b2f69, 0x557ead81, 0x310f4563, 0xbddc5be8, 0x9760f38c, 0x701e0205,
+    0x00157244, 0x14912826, 0xdc4ca32b, 0x67b196de, 0x5db292e8, 0x8c1b406b, 0x01f34075, 0xfa2520f7,
+    0x73bc37ab, 0x1e18bc30, 0xfe2c6cb3, 0x20c522d0, 0x5639e3db, 0x942bda35, 0x899af9d1, 0xced44035,
+    0x98cc025b, 0x255f5771, 0x70fefa24, 0xe928fa4d, 0x2c030405, 0xb9325590, 0x20cb63bd, 0xa166305d,
+    0x80e52c0a, 0xa8fafe2f, 0x1ad13f7d, 0xcfaf3685, 0x6c83a199, 0x7d26718a, 0xde5dfcd9, 0x79cf7355,
+    0x8979d7fb, 0xebf8c55e, 0xebe408e4, 0xcd2affba, 0xe483be6e, 0xe239d6de, 0x5dc1e9e0, 0x0473931f,
+    0x851b097c, 0xac5db249, 0x09c0f9f2, 0xd8d2f134, 0xe6f38e41, 0xb1c71bf1, 0x52b6e4db, 0x07224424,
+    0x6cf73e85, 0x4f25d89c, 0x782a7d74, 0x10a68dcd, 0x3a868189, 0xd570d2dc, 0x69630745, 0x9542ed86,
+    0x331cd6b2, 0xa84b5b28, 0x07879c9d, 0x38372f64, 0x7185db11, 0x25ba7c83, 0x01061523, 0xe6792f9f,
+    0xe5df07d1, 0x4321b47f, 0x7d2469d8, 0x1a3a4f90, 0x48be29a3, 0x669071af, 0x8ec8dd31, 0x0810bfbf,
+    0x813a06b4, 0x68538345, 0x65865ddc, 0x43a71b8e, 0x78619a56, 0x5a34451d, 0x5bdaa3ed, 0x71edc7e9,
+    0x17ac9a20, 0x78d10bfa, 0x6c1e7f35, 0xd51839d9, 0x240cbc51, 0x33513cc1, 0xd2b4f795, 0xccaa8186,
+    0x0babe682, 0xa33cf164, 0x18c643ea, 0xc1ca105f, 0x9959147a, 0x6d3d94de, 0x0b654fbe, 0xed902ca0,
+    0x7d835cb5, 0x99ba1509, 0x6445c922, 0x495e76c2, 0xf07194bc, 0xa1631d7e, 0x677076a5, 0x89fffe35,
+    0x1a49bcf3, 0x8e6c948a, 0x0144c917, 0x8d93aea1, 0x16f87ddf, 0xc8f25d49, 0x1fb11297, 0x27e750cd,
+    0x2f422da1, 0xdee89a77, 0x1534c643, 0x457b7b8b, 0xaf172f7a, 0x6b9b09d6, 0x33573f7f, 0xf14e15c4,
+    0x526467d5, 0xaf488241, 0x87c3ee0d, 0x33be490c, 0x95aa6e52, 0x43ec242e, 0xd77de99b, 0xd018334f,
+    0x5b78d407, 0x498eb66b, 0xb1279fa8, 0xb38b0ea6, 0x90718376, 0xe325dee2, 0x8e2f2cba, 0xcaa5bdec,
+    0x9d652c56, 0xad68f5cb, 0xa77591af, 0x88e37ee8, 0xf8faa221, 0xfcbbbe47, 0x4f407786, 0xaf393889,
+    0xf444a1d9, 0x15ae1a2f, 0x40aa7097, 0x6f9486ac, 0x29d232a3, 0xe47609e9, 0xe8b631ff, 0xba8565f4,
+    0x11288749, 0x46c9a838, 0xeb1b7cd8, 0xf516bbb1, 0xfb74fda0, 0x010996e6, 0x4c994653, 0x1d889512,
+    0x53dcd9a3, 0xdd074697, 0x1e78e17c, 0x637c98bf, 0x930bb219, 0xcf7f75b0, 0xcb9355fb, 0x9e623009,
+    0xe466d82c, 0x28f968d3, 0xfeb385d9, 0x238e026c, 0xb8ed0560, 0x0c6a027a, 0x3d6fec4b, 0xbb4b2ec2,
+    0xe715031c, 0xeded011d, 0xcdc4d3b9, 0xc456fc96, 0xdd0eea20, 0xb3df8ec9, 0x12351993, 0xd9cbb01c,
+    0x603147a2, 0xcf37d17d, 0xf7fcd9dc, 0xd8556fa3, 0x104c8131, 0x13152774, 0xb4715811, 0x6a72c2c9,
+    0xc5ae37bb, 0xa76ce12a, 0x8150d8f3, 0x2ec29218, 0xa35f0984, 0x48c0647e, 0x0b5ff98c, 0x71893f7b,
+];
+
+#[cfg(test)]
+mod tests {
+    use crate::buzhash::{borg_sizes, fingerprint, Chunker, Config};
+    use crate::{generate_data, ChunkerError, SizeParams};
+
+    #[test]
+    fn borders_are_cut_where_window_fingerprint_matches() {
+        let data = generate_data(1024 * 1024);
+        let config = Config::new(64, 0x1234_5678);
+
+        for (sizes, mask) in [
+            (SizeParams::new(8192, 8192 + 4096, 65536), 4095),
+            (SizeParams::new(1024, 1024 + 8, 65536), 7),
+        ] {
+            let is_cut = |start: usize| {
+                fingerprint(&data[start..start + config.window_size], &config.table) & mask == 0
+            };
+
+            let chunks = Chunker::new(&data, sizes, config.clone()).collect::<Vec<_>>();
+            for chunk in &chunks {
+                let end = chunk.pos + chunk.len;
+                if end == data.len() {
+                    continue;
+                }
+
+                assert!(chunk.len == sizes.max || is_cut(end));
+
+                // windows reaching max size are not tested
+                let last = end.min(chunk.pos + sizes.max - config.window_size);
+                assert!((chunk.pos + sizes.min..last).all(|start| !is_cut(start)));
+            }
+
+            // window starting at min size is tested too, which often matches a short mask
+            assert!(mask > 7 || chunks.iter().any(|chunk| chunk.len == sizes.min));
+        }
+    }
+
+    #[test]
+    fn seed_changes_borders() {
+        let data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(4096, 8192, 65536);
+
+        let chunks = |seed| Chunker::new(&data, sizes, Config::new(48, seed)).collect::<Vec<_>>();
+        assert_eq!(chunks(1), chunks(1));
+        assert_ne!(chunks(1), chunks(2));
+    }
+
+    /// Chunks borg's chunker gives with `Chunker(seed, min_exp, max_exp, mask_bits, window_size)`.
+    fn borg_chunks(data: &[u8], seed: u32, params: [usize; 4]) -> Vec<&[u8]> {
+        let [min_exp, max_exp, mask_bits, window_size] = params;
+        let sizes = borg_sizes(min_exp, max_exp, mask_bits).unwrap();
+        let config = Config::new(window_size, seed);
+
+        Chunker::try_new(data, sizes, config)
+            .unwrap()
+            .map(|chunk| &data[chunk.pos..chunk.pos + chunk.len])
+            .collect()
+    }
+
+    #[test]
+    fn borg_test_vectors_are_reproduced() {
+        // from borg's chunker tests
+        let table = |seed| Config::new(16, seed).table;
+        assert_eq!(fingerprint(b"abcdefghijklmnop", &table(0)), 3795437769);
+        assert_eq!(fingerprint(b"abcdefghijklmnop", &table(1)), 3795400502);
+
+        let data = b"foobarboobaz".repeat(3);
+        let cases: [(u32, [usize; 4], &[&str]); 9] = [
+            (
+                0,
+                [1, 23, 2, 2],
+                &["fooba", "rboobaz", "fooba", "rboobaz", "fooba", "rboobaz"],
+            ),
+            (
+                1,
+                [1, 23, 2, 2],
+                &[
+                    "fo", "obarb", "oob", "azf", "oobarb", "oob", "azf", "oobarb", "oobaz",
+                ],
+            ),
+            (
+                2,
+                [1, 23, 2, 2],
+                &[
+                    "foob",
+                    "ar",
+                    "boobazfoob",
+                    "ar",
+                    "boobazfoob",
+                    "ar",
+                    "boobaz",
+                ],
+            ),
+            (0, [2, 23, 2, 3], &["foobarboobazfoobarboobazfoobarboobaz"]),
+            (
+                1,
+                [2, 23, 2, 3],
+                &["foobar", "boobazfo", "obar", "boobazfo", "obar", "boobaz"],
+            ),
+            (
+                2,
+                [2, 23, 2, 3],
+                &["foob", "arboobaz", "foob", "arboobaz", "foob", "arboobaz"],
+            ),
+            (0, [3, 23, 2, 3], &["foobarboobazfoobarboobazfoobarboobaz"]),
+            (
+                1,
+                [3, 23, 2, 3],
+                &["foobarbo", "obazfoobar", "boobazfo", "obarboobaz"],
+            ),
+            (
+                2,
+                [3, 23, 2, 3],
+                &["foobarboobaz", "foobarboobaz", "foobarboobaz"],
+            ),
+        ];
+        for (seed, params, expected) in cases {
+            let expected = expected
+                .iter()
+                .map(|chunk| chunk.as_bytes())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                borg_chunks(&data, seed, params),
+                expected,
+                "{seed} {params:?}"
+            );
+        }
+
+        // data of borg's test_chunkpoints_unchanged, whose overall hash of chunks
+        // for all its parameters is reproduced by these chunks
+        let mut x = 1u32;
+        let data = (0..100_000)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff;
+                x as u8
+            })
+            .collect::<Vec<_>>();
+
+        for (seed, params, count, prefix) in [
+            (
+                1849058162,
+                [7, 15, 4, 65],
+                703,
+                [139, 142, 155, 130, 138, 143, 129, 153],
+            ),
+            (
+                1234567653,
+                [4, 15, 4, 129],
+                2731,
+                [27, 29, 73, 44, 53, 18, 27, 18],
+            ),
+            (
+                1849058162,
+                [6, 15, 4, 129],
+                1171,
+                [87, 94, 79, 83, 94, 79, 83, 94],
+            ),
+        ] {
+            let lengths = borg_chunks(&data, seed, params)
+                .iter()
+                .map(|chunk| chunk.len())
+                .collect::<Vec<_>>();
+            assert_eq!(lengths.len(), count);
+            assert_eq!(lengths[..8], prefix);
+        }
+    }
+
+    #[test]
+    fn borg_params_are_parsed() {
+        let (sizes, config) = Config::from_borg_params("buzhash,19,23,21,4095", 0).unwrap();
+        assert_eq!(
+            sizes,
+            SizeParams::new(1 << 19, (1 << 19) + (1 << 21), 1 << 23)
+        );
+        assert_eq!(config, Config::borg(0));
+        assert_eq!(sizes, Chunker::default_sizes());
+
+        assert!(Config::from_borg_params("12,23,16,4095", 7).is_ok());
+        assert!(Config::from_borg_params("buzhash,19,23,21", 0).is_err());
+        assert!(Config::from_borg_params("fixed,4194304", 0).is_err());
+        assert!(matches!(
+            Config::from_borg_params("19,23,24,4095", 0),
+            Err(ChunkerError::InvalidSizes { .. })
+        ));
+        assert!(matches!(
+            Config::from_borg_params("10,12,11,3072", 0),
+            Err(ChunkerError::InvalidSizes { .. })
+        ));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::SizeParams;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Error returned when chunker parameters cannot be used by the algorithm.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChunkerError {
+    /// Size parameters are inconsistent or not supported by the algorithm.
+    InvalidSizes {
+        sizes: SizeParams,
+        reason: &'static str,
+    },
+    /// Minimum chunk size is too small for the windows of the algorithm.
+    MinTooSmall { min: usize, required: usize },
+    /// Algorithm-specific parameter has an unsupported value.
+    InvalidParameter {
+        name: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl Display for ChunkerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkerError::InvalidSizes { sizes, reason } => {
+                write!(f, "invalid chunk sizes {}: {}", sizes, reason)
+            }
+            ChunkerError::MinTooSmall { min, required } => write!(
+                f,
+                "minimum chunk size {} is too small, at least {} is required",
+                min, required
+            ),
+            ChunkerError::InvalidParameter { name, reason } => {
+                write!(f, "invalid parameter `{}`: {}", name, reason)
+            }
+        }
+    }
+}
+
+impl Error for ChunkerError {}
@@ -0,0 +1,452 @@
+use crate::supercdc::{gear_table, shifted_table};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+
+const MIN_CHUNK_SIZE: usize = 1024 * 2;
+const AVG_CHUNK_SIZE: usize = 1024 * 8;
+const MAX_CHUNK_SIZE: usize = 1024 * 64;
+
+// Masks taken from https://github.com/nlfiedler/fastcdc-rs,
+// indexed by the binary logarithm of the average chunk size.
+const MASKS: [u64; 26] = [
+    0,                  // padding
+    0,                  // padding
+    0,                  // padding
+    0,                  // padding
+    0,                  // padding
+    0x0000000001804110, // unused except for NC 3
+    0x0000000001803110, // 64B
+    0x0000000018035100, // 128B
+    0x0000001800035300, // 256B
+    0x0000019000353000, // 512B
+    0x0000590003530000, // 1KB
+    0x0000d90003530000, // 2KB
+    0x0000d90103530000, // 4KB
+    0x0000d90303530000, // 8KB
+    0x0000d90313530000, // 16KB
+    0x0000d90f03530000, // 32KB
+    0x0000d90303537000, // 64KB
+    0x0000d90703537000, // 128KB
+    0x0000d90707537000, // 256KB
+    0x0000d91707537000, // 512KB
+    0x0000d91747537000, // 1MB
+    0x0000d91767537000, // 2MB
+    0x0000d93767537000, // 4MB
+    0x0000d93777537000, // 8MB
+    0x0000d93777577000, // 16MB
+    0x0000db3777577000, // unused except for NC 3
+];
+
+/// Smallest average size accepted by the reference implementation.
+const MIN_AVERAGE_SIZE: usize = 256;
+
+/// Variant of the algorithm.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Version {
+    /// Original algorithm, which hashes one byte per step.
+    V2016,
+    /// Algorithm from the 2020 paper, which rolls two bytes per step.
+    V2020,
+}
+
+/// Normalized chunking level, which moves the small and large masks
+/// that many bits away from the average chunk size.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Normalization {
+    Level0,
+    Level1,
+    Level2,
+    Level3,
+}
+
+/// Parameters of FastCDC: the variant, the normalization level and an optional secret key
+/// that the Gear table is derived from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    version: Version,
+    normalization: Normalization,
+    key: Option<[u8; 32]>,
+}
+
+impl Config {
+    pub fn new(version: Version, normalization: Normalization) -> Self {
+        Self {
+            version,
+            normalization,
+            key: None,
+        }
+    }
+
+    /// Returns the config that derives the Gear table from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(Version::V2020, Normalization::Level1)
+    }
+}
+
+impl Normalization {
+    pub fn bits(&self) -> usize {
+        match self {
+            Normalization::Level0 => 0,
+            Normalization::Level1 => 1,
+            Normalization::Level2 => 2,
+            Normalization::Level3 => 3,
+        }
+    }
+}
+
+/// FastCDC chunker producing the same borders as the reference implementation
+/// at https://github.com/nlfiedler/fastcdc-rs.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    sizes: SizeParams,
+    version: Version,
+    mask_s: u64,
+    mask_l: u64,
+    gear: Box<[u64; 256]>,
+    gear_ls: Box<[u64; 256]>,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: MIN_CHUNK_SIZE,
+            avg: AVG_CHUNK_SIZE,
+            max: MAX_CHUNK_SIZE,
+        }
+    }
+
+    /// Creates a new chunker.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no masks for the average size at the given normalization level.
+    pub fn new(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        version: Version,
+        normalization: Normalization,
+    ) -> Self {
+        Self::with_key(buf, sizes, version, normalization, None)
+    }
+
+    /// Creates a new chunker that uses the Gear table derived from a secret `key` if it is given.
+    ///
+    /// Chunks match the reference implementation only without a key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no masks for the average size at the given normalization level.
+    pub fn with_key(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        version: Version,
+        normalization: Normalization,
+        key: Option<&[u8; 32]>,
+    ) -> Self {
+        let (mask_s, mask_l) = masks(sizes, normalization).unwrap_or_else(|e| panic!("{}", e));
+        let gear = gear_table(key);
+        Self {
+            buf,
+            pos: 0,
+            sizes,
+            version,
+            mask_s,
+            mask_l,
+            gear_ls: shifted_table(&gear),
+            gear,
+        }
+    }
+
+    /// Creates a new chunker if the parameters can be used by the algorithm.
+    pub fn try_new(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        version: Version,
+        normalization: Normalization,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Config::new(version, normalization))?;
+        Ok(Self::new(buf, sizes, version, normalization))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            pos: 0,
+            sizes: self.sizes,
+            version: self.version,
+            mask_s: self.mask_s,
+            mask_l: self.mask_l,
+            gear: self.gear,
+            gear_ls: self.gear_ls,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max
+    }
+
+    fn find_border(&self, buf: &[u8]) -> usize {
+        if buf.len() <= self.sizes.min {
+            return buf.len();
+        }
+
+        let remaining = min(self.sizes.max, buf.len());
+        let center = min(self.sizes.avg, buf.len());
+
+        match self.version {
+            Version::V2016 => self.find_border_2016(buf, center, remaining),
+            Version::V2020 => self.find_border_2020(buf, center, remaining),
+        }
+    }
+
+    fn find_border_2016(&self, buf: &[u8], center: usize, remaining: usize) -> usize {
+        let mut fingerprint: u64 = 0;
+        let mut pos = self.sizes.min;
+
+        while pos < center {
+            fingerprint = (fingerprint << 1).wrapping_add(self.gear[buf[pos] as usize]);
+            if fingerprint & self.mask_s == 0 {
+                return pos;
+            }
+            pos += 1;
+        }
+
+        while pos < remaining {
+            fingerprint = (fingerprint << 1).wrapping_add(self.gear[buf[pos] as usize]);
+            if fingerprint & self.mask_l == 0 {
+                return pos;
+            }
+            pos += 1;
+        }
+
+        remaining
+    }
+
+    fn find_border_2020(&self, buf: &[u8], center: usize, remaining: usize) -> usize {
+        let mask_s_ls = self.mask_s << 1;
+        let mask_l_ls = self.mask_l << 1;
+
+        let mut fingerprint: u64 = 0;
+        let mut pos = self.sizes.min / 2;
+
+        while pos < center / 2 {
+            let a = pos * 2;
+            fingerprint = (fingerprint << 2).wrapping_add(self.gear_ls[buf[a] as usize]);
+            if fingerprint & mask_s_ls == 0 {
+                return a;
+            }
+            fingerprint = fingerprint.wrapping_add(self.gear[buf[a + 1] as usize]);
+            if fingerprint & self.mask_s == 0 {
+                return a + 1;
+            }
+            pos += 1;
+        }
+
+        while pos < remaining / 2 {
+            let a = pos * 2;
+            fingerprint = (fingerprint << 2).wrapping_add(self.gear_ls[buf[a] as usize]);
+            if fingerprint & mask_l_ls == 0 {
+                return a;
+            }
+            fingerprint = fingerprint.wrapping_add(self.gear[buf[a + 1] as usize]);
+            if fingerprint & self.mask_l == 0 {
+                return a + 1;
+            }
+            pos += 1;
+        }
+
+        remaining
+    }
+}
+
+/// Selects the small and large masks for the average size, rounding its logarithm
+/// to the nearest integer like the reference implementation.
+fn masks(sizes: SizeParams, normalization: Normalization) -> Result<(u64, u64), ChunkerError> {
+    let bits = (sizes.avg as f64).log2().round() as usize;
+    let level = normalization.bits();
+
+    // masks below 64B are used only as large masks of 256B chunks at the highest level
+    match MASKS.get(bits + level) {
+        Some(&mask_s) if sizes.avg >= MIN_AVERAGE_SIZE => Ok((mask_s, MASKS[bits - level])),
+        _ => Err(ChunkerError::InvalidSizes {
+            sizes,
+            reason: "avg is not supported at this normalization level",
+        }),
+    }
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = self.find_border(&self.buf[self.pos..]);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_key(
+            buf,
+            sizes,
+            config.version,
+            config.normalization,
+            config.key(),
+        )
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        // the 2020 variant starts hashing at min rounded down to an even number,
+        // which has to leave at least one byte in the chunk
+        sizes.check_with_min(2)?;
+        masks(sizes, config.normalization).map(|_| ())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fastcdc::{Chunker, Normalization, Version};
+    use crate::{generate_data, ChunkerError, SizeParams};
+
+    const LEVELS: [Normalization; 4] = [
+        Normalization::Level0,
+        Normalization::Level1,
+        Normalization::Level2,
+        Normalization::Level3,
+    ];
+
+    fn reference_chunks(
+        data: &[u8],
+        sizes: SizeParams,
+        version: Version,
+        level: Normalization,
+    ) -> Vec<(usize, usize)> {
+        let (min, avg, max) = (sizes.min as u32, sizes.avg as u32, sizes.max as u32);
+        match version {
+            Version::V2016 => {
+                let level = match level {
+                    Normalization::Level0 => fastcdc::v2016::Normalization::Level0,
+                    Normalization::Level1 => fastcdc::v2016::Normalization::Level1,
+                    Normalization::Level2 => fastcdc::v2016::Normalization::Level2,
+                    Normalization::Level3 => fastcdc::v2016::Normalization::Level3,
+                };
+                fastcdc::v2016::FastCDC::with_level(data, min, avg, max, level)
+                    .map(|chunk| (chunk.offset, chunk.length))
+                    .collect()
+            }
+            Version::V2020 => {
+                let level = match level {
+                    Normalization::Level0 => fastcdc::v2020::Normalization::Level0,
+                    Normalization::Level1 => fastcdc::v2020::Normalization::Level1,
+                    Normalization::Level2 => fastcdc::v2020::Normalization::Level2,
+                    Normalization::Level3 => fastcdc::v2020::Normalization::Level3,
+                };
+                fastcdc::v2020::FastCDC::with_level(data, min, avg, max, level)
+                    .map(|chunk| (chunk.offset, chunk.length))
+                    .collect()
+            }
+        }
+    }
+
+    #[test]
+    fn fastcdc_matches_reference_implementation() {
+        let data = generate_data(2 * 1024 * 1024);
+
+        for sizes in [
+            Chunker::default_sizes(),
+            SizeParams::new(2048, 4096, 8192),
+            SizeParams::new(4095, 12000, 65535),
+            SizeParams::new(64, 256, 1024),
+        ] {
+            for version in [Version::V2016, Version::V2020] {
+                for level in LEVELS {
+                    let chunks = Chunker::new(&data, sizes, version, level)
+                        .map(|chunk| (chunk.pos, chunk.len))
+                        .collect::<Vec<_>>();
+                    let expected = reference_chunks(&data, sizes, version, level);
+                    assert_eq!(chunks, expected, "{} {:?} {:?}", sizes, version, level);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normalization_narrows_chunk_size_distribution() {
+        let data = generate_data(2 * 1024 * 1024);
+        let sizes = SizeParams::new(1024, 8192, 65536);
+
+        let deviation = |level| {
+            let lengths = Chunker::new(&data, sizes, Version::V2020, level)
+                .map(|chunk| chunk.len as f64)
+                .collect::<Vec<_>>();
+            let mean = lengths.iter().sum::<f64>() / lengths.len() as f64;
+            let variance =
+                lengths.iter().map(|len| (len - mean).powi(2)).sum::<f64>() / lengths.len() as f64;
+            variance.sqrt() / mean
+        };
+
+        assert!(deviation(Normalization::Level3) < deviation(Normalization::Level0));
+    }
+
+    #[test]
+    fn unsupported_average_size_is_rejected() {
+        let sizes = SizeParams::new(16, 32, 1024);
+        assert!(matches!(
+            Chunker::try_new(&[], sizes, Version::V2020, Normalization::Level0),
+            Err(ChunkerError::InvalidSizes { .. })
+        ));
+        let sizes = SizeParams::new(64, 255, 1024);
+        assert!(Chunker::try_new(&[], sizes, Version::V2020, Normalization::Level0).is_err());
+        let sizes = SizeParams::new(64, 256, 1024);
+        assert!(Chunker::try_new(&[], sizes, Version::V2020, Normalization::Level3).is_ok());
+
+        let sizes = SizeParams::new(1 << 20, 1 << 24, 1 << 25);
+        assert!(Chunker::try_new(&[], sizes, Version::V2016, Normalization::Level1).is_ok());
+        assert!(Chunker::try_new(&[], sizes, Version::V2016, Normalization::Level2).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+
+const BLOCK_SIZE: usize = 1024 * 8;
+
+/// Chunker that cuts data into blocks of `avg` bytes, ignoring the content.
+///
+/// Only the last chunk can be shorter. `min` and `max` sizes are not used.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    sizes: SizeParams,
+    block_size: usize,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: BLOCK_SIZE,
+            avg: BLOCK_SIZE,
+            max: BLOCK_SIZE,
+        }
+    }
+
+    /// Creates a new chunker, `avg` of zero is treated as one as the chunks would be empty otherwise.
+    pub fn new(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            sizes,
+            block_size: sizes.avg.max(1),
+        }
+    }
+
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &())?;
+        Ok(Self::new(buf, sizes))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            pos: 0,
+            sizes: self.sizes,
+            block_size: self.block_size,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.block_size
+    }
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = min(self.block_size, self.buf.len() - self.pos);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = ();
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {}
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, _config: Self::Config) -> Self {
+        Chunker::new(buf, sizes)
+    }
+
+    fn validate(sizes: SizeParams, _config: &Self::Config) -> Result<(), ChunkerError> {
+        sizes.check_with_min(0)?;
+
+        if sizes.avg == 0 {
+            return Err(ChunkerError::InvalidSizes {
+                sizes,
+                reason: "avg is the block size and has to be positive",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixed::Chunker;
+    use crate::{Chunk, SizeParams};
+
+    #[test]
+    fn data_is_cut_into_blocks_of_avg_size() {
+        let data = vec![0; 10_000];
+        let chunks = Chunker::new(&data, SizeParams::new(1000, 4096, 8192)).collect::<Vec<_>>();
+
+        assert_eq!(
+            chunks,
+            vec![
+                Chunk::new(0, 4096),
+                Chunk::new(4096, 4096),
+                Chunk::new(8192, 1808)
+            ]
+        );
+        assert!(Chunker::try_new(&data, SizeParams::new(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn zero_avg_is_clamped_to_one_byte() {
+        let data = vec![0; 3];
+        let chunks = Chunker::new(&data, SizeParams::new(0, 0, 0)).collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            vec![Chunk::new(0, 1), Chunk::new(1, 1), Chunk::new(2, 1)]
+        );
+    }
+}
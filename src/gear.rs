@@ -0,0 +1,186 @@
+use crate::supercdc::gear_table;
+use crate::{cut_mask, Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+
+const MIN_CHUNK_SIZE: usize = 1024 * 4;
+const AVG_CHUNK_SIZE: usize = 1024 * 8;
+const MAX_CHUNK_SIZE: usize = 1024 * 64;
+
+/// Parameters of the Gear-based chunker, which consist of an optional secret key
+/// that the Gear table is derived from.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    key: Option<[u8; 32]>,
+}
+
+impl Config {
+    /// Returns the config that derives the Gear table from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self { key: Some(key) }
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+}
+
+/// Gear-based chunker, which rolls one byte per step and cuts where the fingerprint matches the mask.
+///
+/// The mask uses the highest bits of the fingerprint, as they depend on the last 64 bytes,
+/// and gives `avg - min` expected cut length after min size, rounded to the nearest power of two.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    sizes: SizeParams,
+    mask: u64,
+    gear: Box<[u64; 256]>,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: MIN_CHUNK_SIZE,
+            avg: AVG_CHUNK_SIZE,
+            max: MAX_CHUNK_SIZE,
+        }
+    }
+
+    pub fn new(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_key(buf, sizes, None)
+    }
+
+    /// Creates a new chunker that uses the Gear table derived from a secret `key` if it is given.
+    pub fn with_key(buf: &'a [u8], sizes: SizeParams, key: Option<&[u8; 32]>) -> Self {
+        let bits = cut_mask(sizes).count_ones();
+        Self {
+            buf,
+            pos: 0,
+            sizes,
+            mask: !u64::MAX.checked_shr(bits).unwrap_or(0),
+            gear: gear_table(key),
+        }
+    }
+
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Config::default())?;
+        Ok(Self::new(buf, sizes))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            pos: 0,
+            sizes: self.sizes,
+            mask: self.mask,
+            gear: self.gear,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max
+    }
+
+    fn find_border(&self, buf: &[u8]) -> usize {
+        if buf.len() <= self.sizes.min {
+            return buf.len();
+        }
+
+        let remaining = min(self.sizes.max, buf.len());
+
+        let mut fingerprint: u64 = 0;
+        for (index, &byte) in buf[..remaining].iter().enumerate().skip(self.sizes.min) {
+            fingerprint = (fingerprint << 1).wrapping_add(self.gear[byte as usize]);
+            if fingerprint & self.mask == 0 {
+                return index + 1;
+            }
+        }
+
+        remaining
+    }
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = self.find_border(&self.buf[self.pos..]);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_key(buf, sizes, config.key())
+    }
+
+    fn validate(sizes: SizeParams, _config: &Self::Config) -> Result<(), ChunkerError> {
+        // fingerprint starts at min size and every chunk takes at least one byte,
+        // so any ordered sizes can be used
+        sizes.check_with_min(0)
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gear::Chunker;
+    use crate::{generate_data, SizeParams};
+
+    #[test]
+    fn average_size_follows_sizes() {
+        let data = generate_data(4 * 1024 * 1024);
+
+        for sizes in [Chunker::default_sizes(), SizeParams::new(1024, 5120, 65536)] {
+            let chunks = Chunker::new(&data, sizes).collect::<Vec<_>>();
+            let average = data.len() / chunks.len();
+            assert!(
+                average.abs_diff(sizes.avg) < sizes.avg / 10,
+                "{} {}",
+                sizes,
+                average
+            );
+        }
+    }
+
+    #[test]
+    fn borders_are_restored_after_inserted_data() {
+        let data = generate_data(1024 * 1024);
+        let mut shifted = generate_data(100);
+        shifted.extend_from_slice(&data);
+
+        let sizes = SizeParams::new(256, 2048, 16384);
+        let ends = |buf: &[u8], offset: usize| {
+            Chunker::new(buf, sizes)
+                .map(|chunk| chunk.pos + chunk.len - offset)
+                .collect::<Vec<_>>()
+        };
+        let original = ends(&data, 0);
+        let moved = ends(&shifted, 100);
+
+        let common = original.iter().filter(|end| moved.contains(end)).count();
+        assert!(common > original.len() * 9 / 10);
+    }
+}
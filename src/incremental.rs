@@ -0,0 +1,127 @@
+use crate::{Algorithm, AnyChunker, Chunk, SizeParams};
+
+/// Push-based chunker for data that arrives in pieces.
+///
+/// Data is passed using `feed`, which returns chunks whose borders are already known.
+/// After all data is fed, `finish` returns the remaining chunks.
+/// Produces the same chunks as the slice-based chunker run over the concatenated data.
+pub struct Chunker {
+    chunker: Option<AnyChunker<'static>>,
+    lookahead: usize,
+    buf: Vec<u8>,
+    start: usize,
+    pos: usize,
+}
+
+impl Chunker {
+    /// Creates a chunker of the given algorithm with default algorithm-specific parameters.
+    pub fn new(algorithm: Algorithm, sizes: SizeParams) -> Self {
+        Self::with_chunker(AnyChunker::new(algorithm, &[], sizes))
+    }
+
+    /// Creates a chunker that uses the algorithm and parameters of `chunker`.
+    ///
+    /// `chunker` is expected to be created over an empty buffer, e.g.
+    /// `ultra::Chunker::new(&[], sizes).into()`.
+    pub fn with_chunker(chunker: AnyChunker<'static>) -> Self {
+        Self {
+            lookahead: chunker.lookahead(),
+            chunker: Some(chunker),
+            buf: Vec::new(),
+            start: 0,
+            pos: 0,
+        }
+    }
+
+    /// Adds `data` to the end of the stream and returns chunks that were completed by it.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Chunk> {
+        if self.start >= self.lookahead {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(data);
+
+        let mut chunks = vec![];
+        while self.buf.len() - self.start >= self.lookahead {
+            match self.next_chunk() {
+                Some(chunk) => chunks.push(chunk),
+                None => break,
+            }
+        }
+        chunks
+    }
+
+    /// Marks the end of the stream and returns the remaining chunks.
+    pub fn finish(mut self) -> Vec<Chunk> {
+        let mut chunks = vec![];
+        while self.start < self.buf.len() {
+            match self.next_chunk() {
+                Some(chunk) => chunks.push(chunk),
+                None => break,
+            }
+        }
+        chunks
+    }
+
+    /// Amount of bytes that were fed, but do not belong to any returned chunk yet.
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    fn next_chunk(&mut self) -> Option<Chunk> {
+        let (chunker, length) = self.chunker.take()?.find_in(&self.buf[self.start..]);
+        self.chunker = Some(chunker);
+        let length = length?;
+
+        let chunk = Chunk::new(self.pos, length);
+        self.start += length;
+        self.pos += length;
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::incremental::Chunker;
+    use crate::{generate_data, Algorithm, AnyChunker, SizeParams};
+
+    #[test]
+    fn fed_data_gives_same_chunks() {
+        let block = generate_data(40_000);
+        let mut data = generate_data(512 * 1024);
+        data.extend_from_slice(&block);
+        data.extend_from_slice(&generate_data(1000));
+        data.extend_from_slice(&block);
+
+        for algorithm in Algorithm::ALL {
+            for sizes in [algorithm.default_sizes(), SizeParams::new(2048, 4096, 8192)] {
+                let mut chunker = Chunker::new(algorithm, sizes);
+
+                let mut chunks = vec![];
+                let mut rest = data.as_slice();
+                while !rest.is_empty() {
+                    let length = rest.len().min(rand::random::<usize>() % 3000 + 1);
+                    chunks.extend(chunker.feed(&rest[..length]));
+                    rest = &rest[length..];
+                }
+                chunks.extend(chunker.finish());
+
+                let expected = AnyChunker::new(algorithm, &data, sizes).collect::<Vec<_>>();
+                assert_eq!(chunks, expected, "{}", algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn pending_data_is_bounded() {
+        let data = generate_data(256 * 1024);
+        let sizes = SizeParams::new(2048, 4096, 8192);
+
+        let mut chunker = Chunker::new(Algorithm::Ultra, sizes);
+        for piece in data.chunks(1500) {
+            chunker.feed(piece);
+            assert!(chunker.pending() < sizes.max + 1500 + 64);
+        }
+        assert!(!chunker.finish().is_empty());
+    }
+}
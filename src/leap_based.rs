@@ -1,15 +1,117 @@
-use crate::{Chunk, SizeParams};
+use crate::rng::{KeyStream, SplitMix64};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
 
 const MIN_CHUNK_SIZE: usize = 1024 * 8;
 const MAX_CHUNK_SIZE: usize = 1024 * 16;
 
 const WINDOW_PRIMARY_COUNT: usize = 22;
 const WINDOW_SECONDARY_COUNT: usize = 2;
-const WINDOW_COUNT: usize = WINDOW_PRIMARY_COUNT + WINDOW_SECONDARY_COUNT;
 
 const WINDOW_SIZE: usize = 180;
 const WINDOW_MATRIX_SHIFT: usize = 42; // WINDOW_MATRIX_SHIFT * 4 < WINDOW_SIZE - 5
 
+// probability that a window is qualified if matrix values are uniformly distributed
+const QUALIFIED_PROBABILITY: f64 = 0.75;
+const MAX_WINDOW_COUNT: usize = 128;
+
+// random projections used to generate EF matrices
+const PROJECTION_ROWS: usize = 255;
+const PROJECTION_GROUP: usize = PROJECTION_ROWS / 5;
+
+/// Values that bytes contribute to the check of a window, one for each of the five checked positions.
+pub type EfMatrix = [[u8; 5]; 256];
+
+/// Window geometry of Leap-based CDC.
+///
+/// A point is a border if `primary_count + secondary_count` windows of `window_size` bytes
+/// ending right before it, each shifted by one byte, are qualified. A window is qualified
+/// if values of five bytes `matrix_shift` bytes apart, taken from the EF matrix, XOR to non-zero.
+///
+/// Chunkers created with `Chunking::with_config` use the rows of the EF matrix in the order
+/// derived from a secret key if it is given.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    primary_count: usize,
+    secondary_count: usize,
+    window_size: usize,
+    matrix_shift: usize,
+    key: Option<[u8; 32]>,
+}
+
+impl Config {
+    pub fn new(
+        primary_count: usize,
+        secondary_count: usize,
+        window_size: usize,
+        matrix_shift: usize,
+    ) -> Self {
+        Self {
+            primary_count,
+            secondary_count,
+            window_size,
+            matrix_shift,
+            key: None,
+        }
+    }
+
+    /// Returns the config that uses the rows of the EF matrix in the order derived from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+
+    /// Creates the config with the amount of windows that gives `avg - min`
+    /// expected cut length after min size, keeping the default window size and shift.
+    ///
+    /// Three quarters of windows are expected to be qualified, as with the default EF matrix,
+    /// so chunk sizes can only be changed in steps of about a third.
+    pub fn for_sizes(sizes: SizeParams) -> Self {
+        let target = sizes.avg.saturating_sub(sizes.min) as f64;
+        let window_count = (1..=MAX_WINDOW_COUNT)
+            .min_by(|&x, &y| {
+                let x = (expected_cut_length(x) - target).abs();
+                x.total_cmp(&(expected_cut_length(y) - target).abs())
+            })
+            .unwrap_or(1);
+
+        let secondary_count = WINDOW_SECONDARY_COUNT.min(window_count / 2);
+        Self::new(
+            window_count - secondary_count,
+            secondary_count,
+            WINDOW_SIZE,
+            WINDOW_MATRIX_SHIFT,
+        )
+    }
+
+    fn window_count(&self) -> usize {
+        self.primary_count + self.secondary_count
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(
+            WINDOW_PRIMARY_COUNT,
+            WINDOW_SECONDARY_COUNT,
+            WINDOW_SIZE,
+            WINDOW_MATRIX_SHIFT,
+        )
+    }
+}
+
+/// Expected distance between the first checked point and the border for the amount of windows,
+/// which is the expected waiting time for `window_count` qualified windows in a row.
+fn expected_cut_length(window_count: usize) -> f64 {
+    let all_qualified = QUALIFIED_PROBABILITY.powi(window_count as i32);
+    (1.0 - all_qualified) / ((1.0 - QUALIFIED_PROBABILITY) * all_qualified)
+}
+
 enum PointStatus {
     Ok,
     Unsatisfied(usize),
@@ -21,6 +123,8 @@ pub struct Chunker<'a> {
     chunk_start: usize,
     has_cut: bool,
     sizes: SizeParams,
+    config: Config,
+    ef_matrix: Box<EfMatrix>,
 }
 
 impl<'a> Chunker<'a> {
@@ -33,33 +137,96 @@ impl<'a> Chunker<'a> {
     }
 
     pub fn new(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_key(buf, sizes, None)
+    }
+
+    /// Creates a new chunker that uses the rows of the EF matrix in the order
+    /// derived from a secret `key` if it is given.
+    pub fn with_key(buf: &'a [u8], sizes: SizeParams, key: Option<&[u8; 32]>) -> Self {
+        Self::with_matrix(buf, sizes, ef_matrix(key))
+    }
+
+    /// Creates a new chunker that uses the given EF matrix, e.g. one made by `generate_ef_matrix`.
+    pub fn with_matrix(buf: &'a [u8], sizes: SizeParams, ef_matrix: Box<EfMatrix>) -> Self {
+        Self::with_params(buf, sizes, Config::default(), ef_matrix)
+    }
+
+    /// Creates a new chunker with the given window geometry and EF matrix.
+    /// The key of `config` is not used, as the matrix is given.
+    pub fn with_params(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        config: Config,
+        ef_matrix: Box<EfMatrix>,
+    ) -> Self {
         Chunker {
             buf,
             position: sizes.min,
             chunk_start: 0,
             has_cut: false,
             sizes,
+            config,
+            ef_matrix,
         }
     }
 
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Self::default_config())?;
+        Ok(Self::new(buf, sizes))
+    }
+
+    /// Creates a new chunker if `sizes` and `config` can be used by the algorithm.
+    pub fn try_with_params(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        config: Config,
+        ef_matrix: Box<EfMatrix>,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &config)?;
+        Ok(Self::with_params(buf, sizes, config, ef_matrix))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            position: self.position - self.chunk_start,
+            chunk_start: 0,
+            has_cut: self.has_cut,
+            sizes: self.sizes,
+            config: self.config,
+            ef_matrix: self.ef_matrix,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + self.config.window_count() + 1
+    }
+
     fn is_point_satisfied(&self) -> PointStatus {
-        // primary check, T<=x<M where T is WINDOW_SECONDARY_COUNT and M is WINDOW_COUNT
-        for i in WINDOW_SECONDARY_COUNT..WINDOW_COUNT {
+        let window_count = self.config.window_count();
+        let secondary_count = self.config.secondary_count;
+        let window_size = self.config.window_size;
+
+        // primary check, T<=x<M where T is secondary count and M is window count
+        for i in secondary_count..window_count {
             if !self
-                .is_window_qualified(&self.buf[self.position - i - WINDOW_SIZE..self.position - i])
+                .is_window_qualified(&self.buf[self.position - i - window_size..self.position - i])
             {
-                // window is WINDOW_SIZE bytes long and moves to the left
-                let leap = WINDOW_COUNT - i;
+                // window is window_size bytes long and moves to the left
+                let leap = window_count - i;
                 return PointStatus::Unsatisfied(leap);
             }
         }
 
         //secondary check, 0<=x<T bytes
-        for i in 0..WINDOW_SECONDARY_COUNT {
+        for i in 0..secondary_count {
             if !self
-                .is_window_qualified(&self.buf[self.position - i - WINDOW_SIZE..self.position - i])
+                .is_window_qualified(&self.buf[self.position - i - window_size..self.position - i])
             {
-                let leap = WINDOW_COUNT - WINDOW_SECONDARY_COUNT - i;
+                let leap = window_count - secondary_count - i;
                 return PointStatus::Unsatisfied(leap);
             }
         }
@@ -68,10 +235,11 @@ impl<'a> Chunker<'a> {
     }
 
     fn is_window_qualified(&self, window: &[u8]) -> bool {
+        let last = self.config.window_size - 1;
         (0..5)
-            .map(|index| window[WINDOW_SIZE - 1 - index * WINDOW_MATRIX_SHIFT]) // init array
+            .map(|index| window[last - index * self.config.matrix_shift]) // init array
             .enumerate()
-            .map(|(index, byte)| EF_MATRIX[byte as usize][index]) // get elements from ef_matrix
+            .map(|(index, byte)| self.ef_matrix[byte as usize][index]) // get elements from ef_matrix
             .fold(0u8, |acc, value| acc ^ value)
             != 0
     }
@@ -81,6 +249,10 @@ impl Iterator for Chunker<'_> {
     type Item = Chunk;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.chunk_start == self.buf.len() {
+            return None;
+        }
+
         if self.position == self.buf.len() {
             return if self.has_cut {
                 None
@@ -127,7 +299,108 @@ impl Iterator for Chunker<'_> {
     }
 }
 
-const EF_MATRIX: [[u8; 5]; 256] = [
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_params(buf, sizes, config, ef_matrix(config.key()))
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        if config.primary_count == 0 || config.secondary_count > config.primary_count {
+            return Err(ChunkerError::InvalidParameter {
+                name: "secondary_count",
+                reason: "has to be at most primary count, which has to be positive",
+            });
+        }
+        if config.window_size <= 5 || config.matrix_shift * 4 >= config.window_size - 5 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "matrix_shift",
+                reason: "four shifts have to be less than window size - 5",
+            });
+        }
+
+        // windows checked at the first point after min size have to lie inside the chunk,
+        // they overlap and reach window count + window size bytes back
+        sizes.check_with_min(config.window_count() + config.window_size)
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+/// Generates an EF matrix from `seed` as described in the Leap-based CDC paper.
+///
+/// Every byte is projected by two random matrices of normally distributed values,
+/// and parities of positive projections in five groups give the bits of its matrix values.
+/// The same seed always gives the same matrix.
+pub fn generate_ef_matrix(seed: u64) -> Box<EfMatrix> {
+    let mut rng = SplitMix64::new(seed);
+    let matrix_e = random_projection(&mut rng);
+    let matrix_f = random_projection(&mut rng);
+
+    let mut ef_matrix = Box::new([[0; 5]; 256]);
+    for (byte, row) in ef_matrix.iter_mut().enumerate() {
+        let e = project_byte(byte as u8, &matrix_e);
+        let f = project_byte(byte as u8, &matrix_f);
+        for (index, value) in row.iter_mut().enumerate() {
+            *value = (u8::from(e[index]) << 1) | u8::from(f[index]);
+        }
+    }
+    ef_matrix
+}
+
+fn random_projection(rng: &mut SplitMix64) -> Vec<[f64; 8]> {
+    (0..PROJECTION_ROWS)
+        .map(|_| [0; 8].map(|_: u8| rng.next_normal()))
+        .collect()
+}
+
+/// Returns parities of the amounts of positive projections of `byte` in each group of rows.
+fn project_byte(byte: u8, projection: &[[f64; 8]]) -> [bool; 5] {
+    let mut positive = [0usize; 5];
+    for (index, row) in projection.iter().enumerate() {
+        let value: f64 = row
+            .iter()
+            .enumerate()
+            .map(|(bit, &number)| {
+                if (byte >> bit) & 1 == 1 {
+                    number
+                } else {
+                    -number
+                }
+            })
+            .sum();
+
+        if value > 0.0 {
+            positive[index / PROJECTION_GROUP] += 1;
+        }
+    }
+
+    positive.map(|count| count % 2 != 0)
+}
+
+/// Returns `EF_MATRIX` with rows shuffled using SHAKE256 output for `key`, or unchanged if there is no key.
+fn ef_matrix(key: Option<&[u8; 32]>) -> Box<EfMatrix> {
+    match key {
+        Some(key) => {
+            let permutation = KeyStream::new(b"leap matrix", key).permutation();
+            Box::new(permutation.map(|byte| EF_MATRIX[byte as usize]))
+        }
+        None => Box::new(EF_MATRIX),
+    }
+}
+
+const EF_MATRIX: EfMatrix = [
     [0, 0, 2, 0, 2],
     [1, 3, 3, 1, 3],
     [2, 0, 0, 3, 2],
@@ -385,3 +658,88 @@ const EF_MATRIX: [[u8; 5]; 256] = [
     [2, 0, 0, 2, 0],
     [3, 3, 1, 3, 1],
 ];
+
+#[cfg(test)]
+mod tests {
+    use crate::leap_based::{generate_ef_matrix, Chunker, Config};
+    use crate::{generate_data, Chunking, SizeParams};
+
+    #[test]
+    fn generated_matrix_depends_only_on_seed() {
+        let matrix = generate_ef_matrix(7);
+        assert_eq!(matrix, generate_ef_matrix(7));
+        assert_ne!(matrix, generate_ef_matrix(8));
+
+        // every value appears about a quarter of the time in each column
+        for column in 0..5 {
+            for value in 0..4 {
+                let count = matrix.iter().filter(|row| row[column] == value).count();
+                assert!((32..96).contains(&count), "{} {} {}", column, value, count);
+            }
+        }
+
+        let data = generate_data(1024 * 1024);
+        let sizes = Chunker::default_sizes();
+        let chunks = |matrix| Chunker::with_matrix(&data, sizes, matrix).collect::<Vec<_>>();
+        assert_eq!(chunks(generate_ef_matrix(7)), chunks(generate_ef_matrix(7)));
+        assert_ne!(
+            chunks(generate_ef_matrix(7)),
+            Chunker::new(&data, sizes).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn window_count_follows_average_size() {
+        assert_eq!(
+            Config::for_sizes(Chunker::default_sizes()),
+            Config::default()
+        );
+
+        let data = generate_data(4 * 1024 * 1024);
+
+        for sizes in [
+            SizeParams::new(4096, 4096 + 1260, 65536),
+            SizeParams::new(2048, 2048 + 16384, 131072),
+        ] {
+            let config = Config::for_sizes(sizes);
+            let chunks = Chunker::try_with_config(&data, sizes, config)
+                .unwrap()
+                .count();
+
+            let average = data.len() / chunks;
+            assert!(
+                average.abs_diff(sizes.avg) < sizes.avg / 5,
+                "{} {}",
+                sizes,
+                average
+            );
+        }
+    }
+
+    #[test]
+    fn short_data_is_one_chunk_and_empty_data_none() {
+        let sizes = Chunker::default_sizes();
+        let data = [7; 100];
+
+        assert_eq!(Chunker::new(&data[..0], sizes).count(), 0);
+        assert_eq!(
+            Chunker::new(&data, sizes)
+                .map(|chunk| chunk.len)
+                .collect::<Vec<_>>(),
+            vec![100]
+        );
+    }
+
+    #[test]
+    fn inconsistent_geometry_is_rejected() {
+        let sizes = Chunker::default_sizes();
+        let validate = |config| Chunker::validate(sizes, &config);
+
+        assert!(validate(Config::new(22, 2, 180, 42)).is_ok());
+        assert!(validate(Config::new(0, 0, 180, 42)).is_err());
+        assert!(validate(Config::new(2, 3, 180, 42)).is_err());
+        assert!(validate(Config::new(22, 2, 180, 44)).is_err());
+        assert!(validate(Config::new(22, 2, 4, 0)).is_err());
+        assert!(validate(Config::new(22, 2, 9000, 42)).is_err());
+    }
+}
@@ -1,14 +1,30 @@
 use std::fmt::{Display, Formatter};
 
 pub mod ae;
+mod any;
+#[cfg(feature = "async")]
+pub mod async_stream;
+pub mod buzhash;
+mod error;
+pub mod fastcdc;
+pub mod fixed;
+pub mod gear;
+pub mod incremental;
 pub mod leap_based;
+pub mod maxp;
 pub mod rabin;
 pub mod ram;
+mod rng;
 pub mod seq;
+pub mod stream;
 pub mod supercdc;
+pub mod tttd;
 pub mod ultra;
 
-#[derive(Debug)]
+pub use any::{Algorithm, AnyChunker};
+pub use error::ChunkerError;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Chunk {
     pub pos: usize,
     pub len: usize,
@@ -20,6 +36,45 @@ impl Chunk {
     }
 }
 
+/// Common interface of all chunkers in the crate.
+///
+/// Allows creating, configuring and running any of the algorithms
+/// from a single generic code path.
+pub trait Chunking<'a>: Iterator<Item = Chunk> + Sized {
+    /// Algorithm-specific parameters that are not covered by `SizeParams`.
+    type Config: Clone;
+
+    /// Returns default chunk sizes of the algorithm.
+    fn default_sizes() -> SizeParams;
+
+    /// Returns default algorithm-specific parameters.
+    fn default_config() -> Self::Config;
+
+    /// Creates a chunker over `buf` with the given sizes and parameters.
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self;
+
+    /// Checks that the sizes and parameters can be used by the algorithm.
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError>;
+
+    /// Returns size parameters the chunker was created with.
+    fn sizes(&self) -> SizeParams;
+
+    /// Creates a chunker over `buf` with the given sizes and default parameters.
+    fn with_sizes(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_config(buf, sizes, Self::default_config())
+    }
+
+    /// Creates a chunker over `buf` if the sizes and parameters are valid for the algorithm.
+    fn try_with_config(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        config: Self::Config,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &config)?;
+        Ok(Self::with_config(buf, sizes, config))
+    }
+}
+
 /// Struct containing size parameters for chunkers:
 /// min, average and max size of chunks.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -34,15 +89,69 @@ impl SizeParams {
     ///
     /// Panics if not (min <= avg && avg <= max && min <= max).
     pub fn new(min: usize, avg: usize, max: usize) -> Self {
-        assert!(min <= avg && avg <= max && min <= max);
+        match Self::try_new(min, avg, max) {
+            Ok(sizes) => sizes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new instance of `SizeParams` struct.
+    ///
+    /// Returns an error if not (min <= avg && avg <= max && min <= max).
+    pub fn try_new(min: usize, avg: usize, max: usize) -> Result<Self, ChunkerError> {
+        let sizes = Self { min, avg, max };
+        sizes.check_order()?;
+        Ok(sizes)
+    }
 
-        Self { min, avg, max }
+    fn check_order(&self) -> Result<(), ChunkerError> {
+        if self.min <= self.avg && self.avg <= self.max {
+            Ok(())
+        } else {
+            Err(ChunkerError::InvalidSizes {
+                sizes: *self,
+                reason: "min <= avg <= max is not satisfied",
+            })
+        }
+    }
+
+    /// Checks that the sizes are ordered and `min` is at least `required`.
+    pub(crate) fn check_with_min(&self, required: usize) -> Result<(), ChunkerError> {
+        self.check_order()?;
+
+        if self.min < required {
+            return Err(ChunkerError::MinTooSmall {
+                min: self.min,
+                required,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn buzhash_default() -> Self {
+        buzhash::Chunker::default_sizes()
+    }
+
+    pub fn fastcdc_default() -> Self {
+        fastcdc::Chunker::default_sizes()
+    }
+
+    pub fn fixed_default() -> Self {
+        fixed::Chunker::default_sizes()
+    }
+
+    pub fn gear_default() -> Self {
+        gear::Chunker::default_sizes()
     }
 
     pub fn leap_default() -> Self {
         leap_based::Chunker::default_sizes()
     }
 
+    pub fn maxp_default() -> Self {
+        maxp::Chunker::default_sizes()
+    }
+
     pub fn rabin_default() -> Self {
         rabin::Chunker::default_sizes()
     }
@@ -51,6 +160,10 @@ impl SizeParams {
         supercdc::Chunker::default_sizes()
     }
 
+    pub fn tttd_default() -> Self {
+        tttd::Chunker::default_sizes()
+    }
+
     pub fn ultra_default() -> Self {
         ultra::Chunker::default_sizes()
     }
@@ -65,3 +178,212 @@ impl Display for SizeParams {
         write!(f, "{}-{}-{}", self.min, self.avg, self.max)
     }
 }
+
+/// Calculates the mask that gives `avg - min` expected cut length after min size,
+/// rounding it to the nearest power of two.
+pub(crate) fn cut_mask(sizes: SizeParams) -> u64 {
+    let target = sizes.avg - sizes.min;
+    if target == 0 {
+        return 0;
+    }
+
+    let upper = target.next_power_of_two();
+    let lower = upper / 2;
+    let length = if upper - target <= target - lower {
+        upper
+    } else {
+        lower
+    };
+
+    (length - 1) as u64
+}
+
+/// Width of values compared by extremum-based chunkers (AE, RAM and SeqCDC).
+///
+/// Values are little-endian numbers read at multiples of the width from the chunk start,
+/// and borders are placed between them, so that UTF-16 code units or fields of fixed-size records
+/// are compared as a whole. Only the last chunk of data can end elsewhere.
+/// Wider values are rarely equal, which helps AE and SeqCDC on data with long runs of equal bytes,
+/// and SeqCDC and RAM on data with few distinct bytes. A sequence of wide values spans more bytes,
+/// so SeqCDC chunks of random data get longer.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ValueWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl ValueWidth {
+    /// Returns the amount of bytes in a value.
+    pub fn bytes(self) -> usize {
+        match self {
+            ValueWidth::U8 => 1,
+            ValueWidth::U16 => 2,
+            ValueWidth::U32 => 4,
+            ValueWidth::U64 => 8,
+        }
+    }
+
+    /// Reads the value at `pos`, missing bytes after the end of `buf` are taken as zeros.
+    pub(crate) fn read(self, buf: &[u8], pos: usize) -> u64 {
+        if self == ValueWidth::U8 {
+            return u64::from(buf[pos]);
+        }
+
+        let available = buf.get(pos..).unwrap_or_default();
+        let length = available.len().min(self.bytes());
+        let mut bytes = [0; 8];
+        bytes[..length].copy_from_slice(&available[..length]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// Generates random data of the given size for tests.
+#[cfg(test)]
+pub(crate) fn generate_data(size: usize) -> Vec<u8> {
+    (0..size).map(|_| rand::random::<u8>()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ae, cut_mask, rabin, ram, seq, Chunk, Chunking, SizeParams, ValueWidth};
+    use rand::Rng;
+
+    /// Generates runs of random bytes that are up to 64 bytes long.
+    fn generate_runs(size: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut data = Vec::with_capacity(size + 64);
+        while data.len() < size {
+            let length = rng.gen_range(1..=64);
+            data.resize(data.len() + length, rng.gen());
+        }
+        data.truncate(size);
+        data
+    }
+
+    /// Generates 512-byte records of up to 256 random bytes padded with zeros.
+    fn generate_sparse(size: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut data = Vec::with_capacity(size + 512);
+        while data.len() < size {
+            let length = rng.gen_range(0..=256);
+            data.extend((0..length).map(|_| rng.gen::<u8>()));
+            data.resize(data.len() + 512 - length, 0);
+        }
+        data.truncate(size);
+        data
+    }
+
+    /// Generates words of lowercase ASCII letters encoded as UTF-16LE.
+    fn generate_utf16(size: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut data = Vec::with_capacity(size + 32);
+        while data.len() < size {
+            for _ in 0..rng.gen_range(1..=10) {
+                data.extend([rng.gen_range(b'a'..=b'z'), 0]);
+            }
+            data.extend([b' ', 0]);
+        }
+        data.truncate(size);
+        data
+    }
+
+    /// Returns the share of chunks cut at max size, without the last chunk.
+    fn share_at_max(chunks: Vec<Chunk>, sizes: SizeParams) -> f64 {
+        let (_, chunks) = chunks.split_last().unwrap();
+        let at_max = chunks.iter().filter(|chunk| chunk.len >= sizes.max).count();
+        at_max as f64 / chunks.len() as f64
+    }
+
+    #[test]
+    fn cut_mask_is_rounded_to_nearest_power_of_two() {
+        assert_eq!(cut_mask(rabin::Chunker::default_sizes()), 16 * 1024 - 1);
+        assert_eq!(cut_mask(SizeParams::new(2048, 4096, 16384)), 2047);
+        assert_eq!(cut_mask(SizeParams::new(3000, 50000, 100000)), 32767);
+        assert_eq!(cut_mask(SizeParams::new(1000, 4000, 16384)), 2047);
+        assert_eq!(cut_mask(SizeParams::new(1000, 4500, 16384)), 4095);
+        assert_eq!(cut_mask(SizeParams::new(4096, 4096, 16384)), 0);
+    }
+
+    /// Returns the share of borders of `data` that are kept, shifted, after `inserted` bytes
+    /// are put at `at`.
+    fn share_kept(
+        chunker: impl Fn(&[u8]) -> Vec<Chunk>,
+        data: &[u8],
+        at: usize,
+        inserted: &[u8],
+    ) -> f64 {
+        let ends = |chunks: Vec<Chunk>| {
+            chunks
+                .iter()
+                .map(|chunk| chunk.pos + chunk.len)
+                .filter(|&end| end > at + inserted.len())
+                .collect::<Vec<_>>()
+        };
+
+        let original = ends(chunker(data));
+        let changed = [&data[..at], inserted, &data[at..]].concat();
+        let shifted = ends(chunker(&changed))
+            .into_iter()
+            .map(|end| end - inserted.len())
+            .collect::<Vec<_>>();
+
+        let kept = original.iter().filter(|end| shifted.contains(end)).count();
+        kept as f64 / original.len() as f64
+    }
+
+    #[test]
+    fn wide_values_cut_runs_padding_and_utf16_by_content() {
+        let size = 2 * 1024 * 1024;
+        let runs = generate_runs(size);
+        let sparse = generate_sparse(size);
+        let utf16 = generate_utf16(size);
+
+        let ae = |value_width| {
+            move |data: &[u8]| {
+                let config = ae::Config::default().with_value_width(value_width);
+                let sizes = ae::Chunker::default_sizes();
+                ae::Chunker::with_config(data, sizes, config).collect::<Vec<_>>()
+            }
+        };
+        let seq = |value_width| {
+            move |data: &[u8]| {
+                let config = seq::Config::default().with_value_width(value_width);
+                let sizes = seq::Chunker::default_sizes();
+                seq::Chunker::new(data, sizes, config).collect::<Vec<_>>()
+            }
+        };
+        let ram = |value_width| {
+            move |data: &[u8]| {
+                let config = ram::Config::default().with_value_width(value_width);
+                let sizes = ram::Chunker::default_sizes();
+                ram::Chunker::with_config(data, sizes, config).collect::<Vec<_>>()
+            }
+        };
+        let ae_at_max =
+            |data, value_width| share_at_max(ae(value_width)(data), ae::Chunker::default_sizes());
+        let seq_at_max =
+            |data, value_width| share_at_max(seq(value_width)(data), seq::Chunker::default_sizes());
+
+        // runs of equal bytes hide the extremum or the sequence, so single bytes are often cut at max size
+        assert!(ae_at_max(&runs, ValueWidth::U64) < ae_at_max(&runs, ValueWidth::U8) / 2.0);
+        assert!(seq_at_max(&runs, ValueWidth::U64) < seq_at_max(&runs, ValueWidth::U8) / 2.0);
+
+        // zero high bytes of UTF-16 break every sequence of single bytes
+        assert_eq!(seq_at_max(&utf16, ValueWidth::U8), 1.0);
+        assert!(seq_at_max(&utf16, ValueWidth::U16) < 0.5);
+
+        // values are aligned to the width, so borders move along with an inserted value
+        for (data, value_width) in [(&sparse, ValueWidth::U64), (&utf16, ValueWidth::U16)] {
+            assert!(ae_at_max(data, value_width) < 0.5);
+            assert!(seq_at_max(data, value_width) < 0.75);
+
+            let inserted = &data[..value_width.bytes()];
+            assert!(share_kept(ae(value_width), data, 4096, inserted) > 0.95);
+            assert!(share_kept(seq(value_width), data, 4096, inserted) > 0.95);
+            assert!(share_kept(ram(value_width), data, 4096, inserted) > 0.95);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::supercdc::gear_table;
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+use std::collections::VecDeque;
+
+const MIN_CHUNK_SIZE: usize = 1024 * 4;
+const AVG_CHUNK_SIZE: usize = 1024 * 8;
+const MAX_CHUNK_SIZE: usize = 1024 * 32;
+const DEFAULT_HORIZON: usize = 1024 * 4;
+
+/// Parameters of MAXP: the horizon, which is the distance in bytes that a local maximum
+/// has to exceed on both sides, and an optional secret key that the Gear table is derived from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    horizon: usize,
+    key: Option<[u8; 32]>,
+}
+
+impl Config {
+    pub fn new(horizon: usize) -> Self {
+        Self { horizon, key: None }
+    }
+
+    /// Returns the config that derives the Gear table from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn horizon(&self) -> usize {
+        self.horizon
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(DEFAULT_HORIZON)
+    }
+}
+
+/// Local maximum chunker (MAXP) as described by Bjørner, Blass and Gurevich.
+///
+/// Every position is given the Gear fingerprint of the bytes up to it as its value.
+/// A border is placed after a position whose value is strictly greater than the values
+/// of all positions within `horizon` bytes on both sides of it.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    sizes: SizeParams,
+    horizon: usize,
+    gear: Box<[u64; 256]>,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: MIN_CHUNK_SIZE,
+            avg: AVG_CHUNK_SIZE,
+            max: MAX_CHUNK_SIZE,
+        }
+    }
+
+    pub fn new(buf: &'a [u8], sizes: SizeParams, horizon: usize) -> Self {
+        Self::with_key(buf, sizes, horizon, None)
+    }
+
+    /// Creates a new chunker that uses the Gear table derived from a secret `key` if it is given.
+    pub fn with_key(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        horizon: usize,
+        key: Option<&[u8; 32]>,
+    ) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            sizes,
+            horizon,
+            gear: gear_table(key),
+        }
+    }
+
+    /// Creates a new chunker if `sizes` and `horizon` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams, horizon: usize) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Config::new(horizon))?;
+        Ok(Self::new(buf, sizes, horizon))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            pos: 0,
+            sizes: self.sizes,
+            horizon: self.horizon,
+            gear: self.gear,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + self.horizon
+    }
+
+    fn find_border(&self, buf: &[u8]) -> usize {
+        if buf.len() <= self.sizes.min {
+            return buf.len();
+        }
+
+        let horizon = self.horizon;
+        let end = min(buf.len(), self.sizes.max + horizon);
+
+        let mut values = Vec::with_capacity(end);
+        let mut fingerprint: u64 = 0;
+
+        // positions of the window in descending order of values, equal values are kept
+        let mut window: VecDeque<usize> = VecDeque::new();
+
+        for (index, &byte) in buf[..end].iter().enumerate() {
+            fingerprint = (fingerprint << 1).wrapping_add(self.gear[byte as usize]);
+            values.push(fingerprint);
+
+            while window
+                .back()
+                .is_some_and(|&back| values[back] < fingerprint)
+            {
+                window.pop_back();
+            }
+            window.push_back(index);
+            while window
+                .front()
+                .is_some_and(|&front| front + 2 * horizon < index)
+            {
+                window.pop_front();
+            }
+
+            // the window now holds all positions within horizon of the center
+            let Some(center) = index.checked_sub(horizon) else {
+                continue;
+            };
+
+            if center + 1 >= self.sizes.min && window[0] == center {
+                let is_strict = window
+                    .get(1)
+                    .is_none_or(|&next| values[next] < values[center]);
+                if is_strict {
+                    return center + 1;
+                }
+            }
+            if center + 1 >= self.sizes.max {
+                return self.sizes.max;
+            }
+        }
+
+        min(buf.len(), self.sizes.max)
+    }
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = self.find_border(&self.buf[self.pos..]);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_key(buf, sizes, config.horizon, config.key())
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        // positions closer than horizon to the chunk start are compared only to the following bytes,
+        // so any ordered sizes can be used
+        sizes.check_with_min(0)?;
+
+        if config.horizon == 0 || config.horizon >= sizes.max {
+            return Err(ChunkerError::InvalidParameter {
+                name: "horizon",
+                reason: "has to be positive and less than max chunk size",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::maxp::Chunker;
+    use crate::supercdc::GEAR;
+    use crate::{generate_data, SizeParams};
+
+    #[test]
+    fn borders_are_placed_after_local_maxima() {
+        let data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(512, 2048, 8192);
+        let horizon = 300;
+
+        for chunk in Chunker::new(&data, sizes, horizon) {
+            if chunk.len == sizes.max || chunk.pos + chunk.len == data.len() {
+                continue;
+            }
+
+            let values = data[chunk.pos..]
+                .iter()
+                .scan(0u64, |fingerprint, &byte| {
+                    *fingerprint = (*fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+                    Some(*fingerprint)
+                })
+                .take(chunk.len + horizon)
+                .collect::<Vec<_>>();
+
+            let center = chunk.len - 1;
+            let start = center.saturating_sub(horizon);
+            assert!((start..center + horizon + 1)
+                .filter(|&index| index != center)
+                .all(|index| values[index] < values[center]));
+        }
+    }
+
+    #[test]
+    fn borders_are_restored_after_changed_data() {
+        let mut data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(512, 2048, 8192);
+        let ends = |buf: &[u8]| {
+            Chunker::new(buf, sizes, 700)
+                .map(|chunk| chunk.pos + chunk.len)
+                .collect::<Vec<_>>()
+        };
+
+        let original = ends(&data);
+        data[1000..1010].fill(0);
+        let changed = ends(&data);
+
+        let common = original.iter().filter(|end| changed.contains(end)).count();
+        assert!(common > original.len() * 9 / 10);
+    }
+
+    #[test]
+    fn borders_are_restored_after_insertion_into_repetitive_text() {
+        // lines often repeat within the horizon, so equal values compete for the local maximum
+        let lines = [
+            "the quick brown fox jumps over the lazy dog\n",
+            "pack my box with five dozen liquor jugs\n",
+            "how vexingly quick daft zebras jump\n",
+            "the five boxing wizards jump quickly\n",
+        ];
+        let text = (0..20_000)
+            .map(|_| {
+                lines[rand::random::<usize>() % lines.len()].repeat(rand::random::<usize>() % 3 + 1)
+            })
+            .collect::<String>()
+            .into_bytes();
+
+        let sizes = SizeParams::new(512, 2048, 8192);
+        let ends = |buf: &[u8]| {
+            Chunker::new(buf, sizes, 700)
+                .map(|chunk| chunk.pos + chunk.len)
+                .collect::<Vec<_>>()
+        };
+
+        let original = ends(&text);
+        let inserted = b"sphinx of black quartz, judge my vow\n";
+        let changed = [&text[..1000], inserted, &text[1000..]].concat();
+        let shifted = ends(&changed)
+            .into_iter()
+            .map(|end| end - inserted.len())
+            .collect::<Vec<_>>();
+
+        let at_max = original
+            .windows(2)
+            .filter(|pair| pair[1] - pair[0] == sizes.max)
+            .count();
+        let common = original.iter().filter(|end| shifted.contains(end)).count();
+        assert!(at_max < original.len() / 10);
+        assert!(common > original.len() * 9 / 10);
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{Chunk, SizeParams};
+use crate::{cut_mask, Chunk, ChunkerError, Chunking, SizeParams};
+
+#[cfg(test)]
+mod go_rand;
+mod polynomial;
+
+pub use polynomial::Polynomial;
 
 // implementation taken from zbox
 // https://github.com/zboxfs/zbox
@@ -12,18 +18,19 @@ const AVG_SIZE: usize = 32 * 1024; // average chunk size, 32k
 const MAX_SIZE: usize = 64 * 1024; // maximum chunk size, 64k
 
 // Irreducible polynomial for Rabin modulus, from pcompress
-const FP_POLY: u64 = 0xbfe6_b8a5_bf37_8d83u64;
-
-// since we will skip MIN_SIZE when sliding window, it only
-// needs to target (AVG_SIZE - MIN_SIZE) cut length,
-// note the (AVG_SIZE - MIN_SIZE) must be 2^n
-const CUT_MASK: u64 = (AVG_SIZE - MIN_SIZE - 1) as u64;
+const FP_POLY: Polynomial = Polynomial::new_unchecked(0xbfe6_b8a5_bf37_8d83);
 
 // rolling hash window constants
 const WIN_SIZE: usize = 16; // must be 2^n
 const WIN_MASK: usize = WIN_SIZE - 1;
-const WIN_SLIDE_OFFSET: usize = 64;
-const WIN_SLIDE_POS: usize = MIN_SIZE - WIN_SLIDE_OFFSET;
+pub(crate) const WIN_SLIDE_OFFSET: usize = 64;
+
+// restic chunker constants
+// https://github.com/restic/chunker
+const RESTIC_WIN_SIZE: usize = 64;
+const RESTIC_MIN_SIZE: usize = 512 * 1024;
+const RESTIC_AVERAGE_BITS: usize = 20;
+const RESTIC_MAX_SIZE: usize = 8 * 1024 * 1024;
 
 pub struct Chunker<'a> {
     buf: &'a [u8],
@@ -32,14 +39,30 @@ pub struct Chunker<'a> {
     len: usize,
     sizes: SizeParams,
     win_slide_pos: usize,
+    cut_mask: u64,
 }
 
 /// Pre-calculated chunker parameters
 #[derive(Clone)]
 pub struct ChunkerParams {
-    poly_pow: u64,     // poly power
-    out_map: Vec<u64>, // pre-computed out byte map, length is 256
-    ir: Vec<u64>,      // irreducible polynomial, length is 256
+    poly: Polynomial, // polynomial the tables are calculated from
+    tables: Tables,
+}
+
+#[derive(Clone)]
+enum Tables {
+    /// Rolling hash from pcompress
+    Pcompress {
+        out_map: Vec<u64>, // pre-computed out byte map, length is 256
+        ir: Vec<u64>,      // irreducible polynomial, length is 256
+    },
+    /// Rabin fingerprint modulo the polynomial, as in restic
+    Rabin {
+        out: Vec<u64>,    // fingerprints of bytes leaving the window, length is 256
+        modulo: Vec<u64>, // remainders of the top byte, length is 256
+        mask: u64,        // bits below the polynomial degree
+        shift: usize,     // polynomial degree - 8
+    },
 }
 
 impl<'a> Chunker<'a> {
@@ -51,30 +74,71 @@ impl<'a> Chunker<'a> {
         }
     }
 
-    pub fn new(buf: &[u8]) -> Chunker {
-        Chunker {
-            buf,
-            pos: 0,
-            params: ChunkerParams::new(),
-            len: buf.len(),
-            sizes: Self::default_sizes(),
-            win_slide_pos: WIN_SLIDE_POS,
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self::with_params(buf, ChunkerParams::new(), Self::default_sizes())
+    }
+
+    /// Sizes used by restic: 512 KiB min, 8 MiB max, 20-bit split mask.
+    pub fn restic_sizes() -> SizeParams {
+        SizeParams {
+            min: RESTIC_MIN_SIZE,
+            avg: RESTIC_MIN_SIZE + (1 << RESTIC_AVERAGE_BITS),
+            max: RESTIC_MAX_SIZE,
         }
     }
 
-    pub fn with_params(buf: &'a [u8], params: ChunkerParams, sizes: SizeParams) -> Self {
-        debug_assert!(sizes.min > WIN_SLIDE_OFFSET);
+    /// Creates a chunker that produces the same chunks as restic's chunker
+    /// using the given polynomial, e.g. the one from the repository config.
+    pub fn restic(buf: &'a [u8], poly: Polynomial) -> Self {
+        Self::with_params(buf, ChunkerParams::restic(poly), Self::restic_sizes())
+    }
 
+    /// Creates a new chunker with the given parameters and sizes.
+    ///
+    /// Since `min` bytes are skipped before searching for the border,
+    /// the cut mask targets `avg - min` cut length. If it is not a power of two,
+    /// it is rounded to the nearest one (ties are rounded up),
+    /// e.g. 3000-50000-100000 sizes result in the average of 3000 + 32768 bytes.
+    pub fn with_params(buf: &'a [u8], params: ChunkerParams, sizes: SizeParams) -> Self {
         Self {
             buf,
             params,
             pos: 0,
             len: buf.len(),
             sizes,
-            win_slide_pos: sizes.min - WIN_SLIDE_OFFSET,
+            win_slide_pos: sizes.min.saturating_sub(WIN_SLIDE_OFFSET),
+            cut_mask: cut_mask(sizes),
         }
     }
 
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_with_params(
+        buf: &'a [u8],
+        params: ChunkerParams,
+        sizes: SizeParams,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &params)?;
+        Ok(Self::with_params(buf, params, sizes))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            params: self.params,
+            pos: 0,
+            len: buf.len(),
+            sizes: self.sizes,
+            win_slide_pos: self.win_slide_pos,
+            cut_mask: self.cut_mask,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + 1
+    }
+
     fn find_border(&mut self) -> Option<usize> {
         if self.len == self.pos {
             return None;
@@ -86,39 +150,22 @@ impl<'a> Chunker<'a> {
             return Some(self.len - pos);
         }
 
-        self.pos += self.win_slide_pos;
+        let buf = &self.buf[self.pos..];
+        let mut fingerprint = self.params.fingerprint();
         let mut chunk_len = self.win_slide_pos;
 
-        let mut win = [0u8; WIN_SIZE];
-        let mut win_idx = 0;
-        let mut roll_hash = 0;
-
-        while self.pos < self.len {
-            let ch = self.buf[self.pos];
-            let out = win[win_idx] as usize;
-            let pushed_out = self.params.out_map[out];
-
-            // calculate Rabin rolling hash
-            roll_hash = (roll_hash * PRIME) & MASK;
-            roll_hash += u64::from(ch);
-            roll_hash = roll_hash.wrapping_sub(pushed_out) & MASK;
-
-            // forward circle window
-            win[win_idx] = ch;
-            win_idx = (win_idx + 1) & WIN_MASK;
-
+        while chunk_len < buf.len() {
+            let checksum = fingerprint.slide(buf[chunk_len]);
             chunk_len += 1;
-            self.pos += 1;
 
-            if chunk_len >= self.sizes.min {
-                let checksum = roll_hash ^ self.params.ir[out];
-
-                if (checksum & CUT_MASK) == 0 || chunk_len >= self.sizes.max {
-                    return Some(chunk_len);
-                }
+            if chunk_len >= self.sizes.min
+                && ((checksum & self.cut_mask) == 0 || chunk_len >= self.sizes.max)
+            {
+                break;
             }
         }
 
+        self.pos += chunk_len;
         Some(chunk_len)
     }
 
@@ -137,62 +184,399 @@ impl Iterator for Chunker<'_> {
     }
 }
 
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = ChunkerParams;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        ChunkerParams::new()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_params(buf, config, sizes)
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        // window starts sliding at most WIN_SLIDE_OFFSET bytes before min size,
+        // so any ordered sizes can be used
+        sizes.check_with_min(0)?;
+
+        let degree = config.poly.degree();
+        if matches!(config.tables, Tables::Rabin { .. }) && !(8..=64).contains(&degree) {
+            return Err(ChunkerError::InvalidParameter {
+                name: "polynomial",
+                reason: "degree has to be between 8 and 64 for Rabin fingerprints",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
 impl ChunkerParams {
+    /// Creates parameters of the rolling hash from pcompress with its polynomial.
     pub fn new() -> Self {
-        let mut cp = ChunkerParams::default();
+        let poly = FP_POLY;
+        let mut out_map = vec![0u64; 256];
+        let mut ir = vec![0u64; 256];
 
         // calculate poly power, it is actually PRIME ^ WIN_SIZE
+        let mut poly_pow = 1;
         for _ in 0..WIN_SIZE {
-            cp.poly_pow = (cp.poly_pow * PRIME) & MASK;
+            poly_pow = (poly_pow * PRIME) & MASK;
         }
 
         // pre-calculate out map table and irreducible polynomial
         // for each possible byte, copy from PCompress implementation
         for i in 0..256 {
-            cp.out_map[i] = (i as u64 * cp.poly_pow) & MASK;
+            out_map[i] = (i as u64 * poly_pow) & MASK;
 
-            let (mut term, mut pow, mut val) = (1u64, 1u64, 1u64);
+            let (mut term, mut pow, mut val) = (1u128, 1u64, 1u64);
             for _ in 0..WIN_SIZE {
-                if (term & FP_POLY) != 0 {
+                if (term & poly.value()) != 0 {
                     val += (pow * i as u64) & MASK;
                 }
                 pow = (pow * PRIME) & MASK;
                 term *= 2;
             }
-            cp.ir[i] = val;
+            ir[i] = val;
+        }
+
+        ChunkerParams {
+            poly,
+            tables: Tables::Pcompress { out_map, ir },
+        }
+    }
+
+    /// Creates parameters of the Rabin fingerprint of a 64-byte window modulo
+    /// a custom irreducible polynomial of degree 8 to 64.
+    ///
+    /// Fingerprints are calculated the same way as in restic's chunker,
+    /// all terms of the polynomial are used.
+    /// Parameters can be persisted by storing the polynomial
+    /// and later restored by passing it to this function.
+    pub fn with_polynomial(poly: Polynomial) -> Self {
+        let append_byte = |hash: u64, byte: u8| -> u64 {
+            polynomial::modulo(((hash as u128) << 8) | byte as u128, poly.value()) as u64
+        };
+
+        // out[b] is the fingerprint of b followed by RESTIC_WIN_SIZE - 1 zero bytes,
+        // adding it removes b from the beginning of the window
+        let out = (0..=255u8)
+            .map(|byte| {
+                (1..RESTIC_WIN_SIZE).fold(append_byte(0, byte), |hash, _| append_byte(hash, 0))
+            })
+            .collect();
+
+        // modulo[b] is the remainder of the top byte b shifted past the degree
+        let degree = poly.degree().max(8) as usize;
+        let modulo = (0..256u128)
+            .map(|byte| polynomial::modulo(byte << degree, poly.value()) as u64)
+            .collect();
+
+        ChunkerParams {
+            poly,
+            tables: Tables::Rabin {
+                out,
+                modulo,
+                mask: (u128::MAX >> (128 - degree)) as u64,
+                shift: degree - 8,
+            },
+        }
+    }
+
+    /// Creates parameters of restic's chunker, which calculates Rabin fingerprint
+    /// of a 64-byte window modulo `poly`, same as `with_polynomial`.
+    ///
+    /// restic uses polynomials of degree 53.
+    pub fn restic(poly: Polynomial) -> Self {
+        Self::with_polynomial(poly)
+    }
+
+    /// Creates parameters using a random irreducible polynomial of degree 53
+    /// derived from `seed`.
+    pub fn random(seed: u64) -> Self {
+        Self::with_polynomial(Polynomial::random(seed))
+    }
+
+    /// Creates parameters of the Rabin fingerprint modulo the polynomial derived from a secret `key`
+    /// with `Polynomial::from_key`, as restic does with the polynomial stored in the repository.
+    ///
+    /// Unlike `ChunkerParams::new`, which uses pcompress' hash over a 16-byte window,
+    /// keyed parameters use restic's hash over a 64-byte window, so they give different chunks
+    /// even apart from the polynomial.
+    /// The polynomial can be stored instead of the key and restored with `ChunkerParams::restic`.
+    pub fn keyed(key: &[u8; 32]) -> Self {
+        Self::restic(Polynomial::from_key(key))
+    }
+
+    /// Returns the polynomial of Rabin fingerprint parameters, which restores them
+    /// when passed to `ChunkerParams::with_polynomial`.
+    ///
+    /// Returns `None` for pcompress parameters of `ChunkerParams::new`, which always use
+    /// the same polynomial and can not be built from another one.
+    pub fn polynomial(&self) -> Option<Polynomial> {
+        match self.tables {
+            Tables::Pcompress { .. } => None,
+            Tables::Rabin { .. } => Some(self.poly),
         }
+    }
 
-        cp
+    /// Creates an empty fingerprint of the window at the beginning of a chunk.
+    pub(crate) fn fingerprint(&self) -> Fingerprint<'_> {
+        let mut fingerprint = Fingerprint {
+            tables: &self.tables,
+            win: [0; RESTIC_WIN_SIZE],
+            win_idx: 0,
+            hash: 0,
+        };
+
+        // restic starts every chunk by sliding in a single byte with value 1
+        if let Tables::Rabin { .. } = self.tables {
+            fingerprint.slide(1);
+        }
+        fingerprint
     }
 }
 
 impl Default for ChunkerParams {
     fn default() -> Self {
-        let mut ret = ChunkerParams {
-            poly_pow: 1,
-            out_map: vec![0u64; 256],
-            ir: vec![0u64; 256],
-        };
-        ret.out_map.shrink_to_fit();
-        ret.ir.shrink_to_fit();
-        ret
+        Self::new()
+    }
+}
+
+/// Rolling fingerprint of a window of bytes, calculated using the tables of `ChunkerParams`.
+pub(crate) struct Fingerprint<'p> {
+    tables: &'p Tables,
+    win: [u8; RESTIC_WIN_SIZE],
+    win_idx: usize,
+    hash: u64,
+}
+
+impl Fingerprint<'_> {
+    /// Slides `ch` into the window and returns the fingerprint that borders are searched by.
+    pub(crate) fn slide(&mut self, ch: u8) -> u64 {
+        let out = self.win[self.win_idx];
+        self.win[self.win_idx] = ch;
+
+        match self.tables {
+            Tables::Pcompress { out_map, ir } => {
+                self.win_idx = (self.win_idx + 1) & WIN_MASK;
+
+                // calculate Rabin rolling hash
+                self.hash = (self.hash * PRIME) & MASK;
+                self.hash += u64::from(ch);
+                self.hash = self.hash.wrapping_sub(out_map[out as usize]) & MASK;
+
+                self.hash ^ ir[out as usize]
+            }
+            Tables::Rabin {
+                out: out_table,
+                modulo,
+                mask,
+                shift,
+            } => {
+                self.win_idx = (self.win_idx + 1) % RESTIC_WIN_SIZE;
+
+                // top byte is shifted past the degree and replaced by its remainder
+                let digest = self.hash ^ out_table[out as usize];
+                let index = digest >> shift;
+                self.hash = (((digest << 8) | u64::from(ch)) & mask) ^ modulo[index as u8 as usize];
+
+                self.hash
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rabin::{Chunker, ChunkerParams};
-    use crate::SizeParams;
+    use crate::rabin::go_rand::GoRand;
+    use crate::rabin::{polynomial, Chunker, ChunkerParams, Polynomial, Tables, RESTIC_WIN_SIZE};
+    use crate::{generate_data, Chunk, SizeParams};
 
     #[test]
     fn rabin_works_with_different_sizes() {
         let sizes = SizeParams::new(3000, 50000, 100000);
 
-        let data = vec![3; 1024 * 1024 * 300];
+        let data = vec![3; 1024 * 1024 * 4];
 
         let chunker = Chunker::with_params(&data, ChunkerParams::default(), sizes);
         for chunk in chunker {
             println!("{:?}", chunk);
         }
     }
+
+    #[test]
+    fn average_size_follows_sizes() {
+        let data = generate_data(4 * 1024 * 1024);
+
+        for (min, avg) in [(2048, 4096), (4096, 8192 + 4096)] {
+            let sizes = SizeParams::new(min, avg, 64 * 1024);
+            let chunks = Chunker::with_params(&data, ChunkerParams::new(), sizes).count();
+
+            let average = data.len() / chunks;
+            assert!(average > avg * 8 / 10 && average < avg * 12 / 10);
+        }
+    }
+
+    #[test]
+    fn params_are_restored_from_polynomial() {
+        let data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(2048, 4096, 16384);
+
+        let params = ChunkerParams::random(7);
+        let value = params.polynomial().unwrap().value();
+        let restored = ChunkerParams::with_polynomial(Polynomial::new(value).unwrap());
+
+        let chunks = Chunker::with_params(&data, params, sizes).collect::<Vec<_>>();
+        let restored_chunks = Chunker::with_params(&data, restored, sizes).collect::<Vec<_>>();
+        let default_chunks = Chunker::with_params(&data, ChunkerParams::new(), sizes);
+
+        assert_eq!(chunks, restored_chunks);
+        assert_ne!(chunks, default_chunks.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn default_params_are_not_empty() {
+        let params = ChunkerParams::default();
+
+        assert_eq!(params.polynomial(), None);
+        match params.tables {
+            Tables::Pcompress { ir, .. } => assert!(ir.iter().skip(1).all(|&value| value != 0)),
+            Tables::Rabin { .. } => panic!("default parameters are not pcompress ones"),
+        }
+    }
+
+    // polynomial used in restic's chunker tests
+    const RESTIC_TEST_POLY: u128 = 0x3DA3358B4DC173;
+
+    #[test]
+    fn restic_splits_null_bytes_at_min_size() {
+        // test vector from restic's chunker: null bytes are split at MinSize
+        // with zero cut fingerprint, even if data length is a multiple of MinSize
+        let sizes = Chunker::restic_sizes();
+        let data = vec![0u8; 4 * sizes.min];
+
+        let poly = Polynomial::new(RESTIC_TEST_POLY).unwrap();
+        let params = ChunkerParams::restic(poly);
+        let chunks = Chunker::restic(&data, poly).collect::<Vec<_>>();
+
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.len == sizes.min));
+        assert!(chunks
+            .iter()
+            .all(|&chunk| restic_cut(&params, &data, chunk) == 0));
+    }
+
+    /// Returns restic's cut fingerprint, which is the one of the last 64 bytes of the chunk.
+    fn restic_cut(params: &ChunkerParams, data: &[u8], chunk: Chunk) -> u64 {
+        let end = chunk.pos + chunk.len;
+        let mut fingerprint = params.fingerprint();
+        data[end - RESTIC_WIN_SIZE..end]
+            .iter()
+            .fold(0, |_, &byte| fingerprint.slide(byte))
+    }
+
+    #[test]
+    fn restic_chunks_match_test_vectors() {
+        // chunks1 from restic's chunker_test.go: lengths and cut fingerprints
+        // of 32 MiB generated by Go's math/rand seeded with 23
+        #[rustfmt::skip]
+        let expected = [
+            (2163460, 0x000b98d4cdf00000), (643703, 0x000d4e8364d00000),
+            (1528956, 0x0015a25c2ef00000), (1955808, 0x00102a8242e00000),
+            (2222372, 0x00045da878000000), (2538687, 0x00198a8179900000),
+            (609606, 0x001d4e8d17100000), (1205738, 0x000a7204dd600000),
+            (959742, 0x00183e71e1400000), (4036109, 0x001fec043c700000),
+            (1525894, 0x000b1574b1500000), (1352720, 0x00018965f2e00000),
+            (811884, 0x00155628aa100000), (1282314, 0x001909a0a1400000),
+            (1318021, 0x001cceb980000000), (948640, 0x0011f7a470a00000),
+            (645464, 0x00030ce2d9400000), (533758, 0x0004435c53c00000),
+            (1128303, 0x0000c48517800000), (800374, 0x000968473f900000),
+            (2453512, 0x001e197c92600000), (2651975, 0x000ae6c868000000),
+            (237392, 0x00184c5825e18636),
+        ];
+
+        let mut rng = GoRand::new(23);
+        let data = (0..8 * 1024 * 1024)
+            .flat_map(|_| rng.next_u32().to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let poly = Polynomial::new(RESTIC_TEST_POLY).unwrap();
+        let params = ChunkerParams::restic(poly);
+        let chunks = Chunker::restic(&data, poly)
+            .map(|chunk| (chunk.len, restic_cut(&params, &data, chunk)))
+            .collect::<Vec<_>>();
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn restic_cuts_where_window_fingerprint_matches() {
+        let data = generate_data(256 * 1024);
+        let sizes = SizeParams::new(1024, 1024 + 512, 8192);
+
+        for poly in [
+            Polynomial::new(RESTIC_TEST_POLY).unwrap(),
+            Polynomial::random_with_degree(1, 64).unwrap(),
+        ] {
+            // fingerprint of the 64-byte window ending at `end`, calculated directly
+            let fingerprint = |end: usize| {
+                data[end - 64..end].iter().fold(0u128, |hash, &byte| {
+                    polynomial::modulo((hash << 8) | byte as u128, poly.value())
+                })
+            };
+            let is_cut = |end: usize| fingerprint(end) & 511 == 0;
+
+            let chunker = Chunker::with_params(&data, ChunkerParams::restic(poly), sizes);
+            for chunk in chunker {
+                let end = chunk.pos + chunk.len;
+                if end == data.len() {
+                    break;
+                }
+
+                assert!(chunk.len == sizes.max || is_cut(end));
+                assert!((chunk.pos + sizes.min..end).all(|end| !is_cut(end)));
+            }
+        }
+    }
+
+    #[test]
+    fn high_terms_of_polynomial_change_chunks() {
+        let data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(2048, 4096, 16384);
+
+        // irreducible polynomial that differs from the first one only above the 16th term
+        let poly = Polynomial::random(3);
+        let other = (16..53)
+            .flat_map(|i| (i + 1..53).map(move |j| (1 << i) | (1 << j)))
+            .find_map(|flipped| Polynomial::new(poly.value() ^ flipped).ok())
+            .unwrap();
+
+        let chunks = |poly| {
+            Chunker::with_params(&data, ChunkerParams::with_polynomial(poly), sizes)
+                .collect::<Vec<_>>()
+        };
+        assert_ne!(chunks(poly), chunks(other));
+    }
+
+    #[test]
+    fn restic_chunks_have_expected_sizes() {
+        let data = generate_data(8 * 1024 * 1024);
+        let sizes = Chunker::restic_sizes();
+
+        let chunks = Chunker::restic(&data, Polynomial::random(1)).collect::<Vec<_>>();
+        let (last, chunks) = chunks.split_last().unwrap();
+
+        assert_eq!(last.pos + last.len, data.len());
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.len >= sizes.min && chunk.len <= sizes.max));
+    }
 }
@@ -0,0 +1,194 @@
+use crate::rng::{KeyStream, SplitMix64};
+use crate::ChunkerError;
+
+const RANDOM_DEGREE: u32 = 53;
+const MAX_DEGREE: u32 = 64;
+const RANDOM_MAX_TRIES: usize = 1_000_000;
+
+/// Polynomial over GF(2) of degree up to 64, coefficients are stored as bits of `u128`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Polynomial(u128);
+
+impl Polynomial {
+    /// Creates a polynomial from its bit representation.
+    ///
+    /// Returns an error if the polynomial is not irreducible or its degree exceeds 64.
+    pub fn new(value: u128) -> Result<Self, ChunkerError> {
+        if value >> (MAX_DEGREE + 1) != 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "polynomial",
+                reason: "degree has to be at most 64",
+            });
+        }
+
+        let polynomial = Polynomial(value);
+        if polynomial.is_irreducible() {
+            Ok(polynomial)
+        } else {
+            Err(ChunkerError::InvalidParameter {
+                name: "polynomial",
+                reason: "has to be irreducible",
+            })
+        }
+    }
+
+    /// Creates a polynomial without checking that it is irreducible.
+    pub(crate) const fn new_unchecked(value: u128) -> Self {
+        Polynomial(value)
+    }
+
+    /// Finds an irreducible polynomial of degree 53, the degree restic uses, from the given seed.
+    pub fn random(seed: u64) -> Self {
+        Self::random_with_degree(seed, RANDOM_DEGREE).unwrap()
+    }
+
+    /// Finds an irreducible polynomial of the given degree from the given seed.
+    ///
+    /// Candidates with the highest and the constant terms set are drawn from a generator
+    /// seeded with `seed` until an irreducible one is found, so the same seed always gives
+    /// the same polynomial. Unlike restic, which draws them from a cryptographic source,
+    /// the seed has to be kept secret if the polynomial should not be guessed.
+    pub fn random_with_degree(seed: u64, degree: u32) -> Result<Self, ChunkerError> {
+        if degree == 0 || degree > MAX_DEGREE {
+            return Err(ChunkerError::InvalidParameter {
+                name: "degree",
+                reason: "has to be between 1 and 64",
+            });
+        }
+
+        let mut rng = SplitMix64::new(seed);
+        Ok(Self::find_irreducible(degree, || rng.next_u64()))
+    }
+
+    /// Finds an irreducible polynomial of degree 53 from SHAKE256 output for a secret `key`,
+    /// so that the polynomial does not reveal the key.
+    pub fn from_key(key: &[u8; 32]) -> Self {
+        let mut stream = KeyStream::new(b"rabin polynomial", key);
+        Self::find_irreducible(RANDOM_DEGREE, || stream.next_u64())
+    }
+
+    /// Draws candidates with the highest and the constant terms set from `next` until one is irreducible.
+    fn find_irreducible(degree: u32, mut next: impl FnMut() -> u64) -> Self {
+        for _ in 0..RANDOM_MAX_TRIES {
+            let mut value = u128::from(next()) & ((1 << degree) - 1);
+            value |= (1 << degree) | 1;
+
+            let polynomial = Polynomial(value);
+            if polynomial.is_irreducible() {
+                return polynomial;
+            }
+        }
+
+        unreachable!("irreducible polynomial of degree {} was not found", degree)
+    }
+
+    pub fn value(&self) -> u128 {
+        self.0
+    }
+
+    /// Returns the degree of the polynomial, zero polynomial has degree -1.
+    pub fn degree(&self) -> i32 {
+        degree(self.0)
+    }
+
+    /// Checks if the polynomial is irreducible using Ben-Or's test.
+    pub fn is_irreducible(&self) -> bool {
+        if self.degree() < 1 {
+            return false;
+        }
+
+        (1..=self.degree() / 2).all(|i| gcd(self.0, qp(i as u32, self.0)) == 1)
+    }
+}
+
+impl From<Polynomial> for u128 {
+    fn from(polynomial: Polynomial) -> Self {
+        polynomial.0
+    }
+}
+
+fn degree(x: u128) -> i32 {
+    127 - x.leading_zeros() as i32
+}
+
+/// Calculates `x mod m`.
+pub(crate) fn modulo(mut x: u128, m: u128) -> u128 {
+    let m_degree = degree(m);
+    while degree(x) >= m_degree {
+        x ^= m << (degree(x) - m_degree);
+    }
+    x
+}
+
+/// Calculates carry-less product of `x` and `y`, which have to be of degree below 64.
+fn multiply(x: u128, y: u128) -> u128 {
+    (0..64)
+        .filter(|i| (y >> i) & 1 == 1)
+        .fold(0u128, |acc, i| acc ^ (x << i))
+}
+
+fn gcd(mut x: u128, mut y: u128) -> u128 {
+    while y != 0 {
+        let rem = modulo(x, y);
+        x = y;
+        y = rem;
+    }
+    x
+}
+
+/// Calculates `(x^(2^p) - x) mod g`.
+fn qp(p: u32, g: u128) -> u128 {
+    let mut res = 2; // start with x
+    for _ in 0..p {
+        res = modulo(multiply(res, res), g);
+    }
+    modulo(res ^ 2, g)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rabin::Polynomial;
+
+    #[test]
+    fn irreducibility_is_detected() {
+        assert!(Polynomial::new(0b111).is_ok());
+        assert!(Polynomial::new(0b1011).is_ok());
+        assert!(Polynomial::new(0x3DA3358B4DC173).is_ok());
+
+        assert!(Polynomial::new(0).is_err());
+        assert!(Polynomial::new(1).is_err());
+        assert!(Polynomial::new(0b101).is_err());
+        assert!(Polynomial::new(0b1001).is_err());
+        assert!(Polynomial::new(0x3DA3358B4DC173 << 1).is_err());
+
+        // x^64 + x^4 + x^3 + x + 1
+        assert!(Polynomial::new((1 << 64) | 0b11011).is_ok());
+        assert!(Polynomial::new((1 << 64) | 0b11010).is_err());
+        assert!(Polynomial::new(1 << 65 | 1).is_err());
+    }
+
+    #[test]
+    fn random_polynomial_depends_on_seed() {
+        let polynomial = Polynomial::random(42);
+
+        assert_eq!(polynomial.degree(), 53);
+        assert!(polynomial.is_irreducible());
+        assert_eq!(polynomial, Polynomial::random(42));
+        assert_ne!(polynomial, Polynomial::random(43));
+
+        let wide = Polynomial::random_with_degree(42, 64).unwrap();
+        assert_eq!(wide.degree(), 64);
+        assert!(wide.is_irreducible());
+        assert!(Polynomial::random_with_degree(42, 65).is_err());
+    }
+
+    #[test]
+    fn key_polynomial_depends_on_key() {
+        let polynomial = Polynomial::from_key(&[1; 32]);
+
+        assert_eq!(polynomial.degree(), 53);
+        assert!(polynomial.is_irreducible());
+        assert_eq!(polynomial, Polynomial::from_key(&[1; 32]));
+        assert_ne!(polynomial, Polynomial::from_key(&[2; 32]));
+    }
+}
@@ -1,7 +1,47 @@
-use crate::{Chunk, SizeParams};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams, ValueWidth};
 
 const MIN_CHUNK_SIZE: usize = 1024 * 8;
 const MAX_CHUNK_SIZE: usize = 1024 * 16;
+const DEFAULT_WINDOW_SIZE: usize = 32;
+
+/// Parameters of RAM: the size of the window that a maximum has to exceed
+/// and the width of compared values, which are single bytes by default.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    window_size: usize,
+    value_width: ValueWidth,
+}
+
+impl Config {
+    pub fn new(window_size: usize) -> Self {
+        Self {
+            window_size,
+            value_width: ValueWidth::U8,
+        }
+    }
+
+    /// Returns the config that compares values of the given width instead of single bytes.
+    pub fn with_value_width(self, value_width: ValueWidth) -> Self {
+        Self {
+            value_width,
+            ..self
+        }
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    pub fn value_width(&self) -> ValueWidth {
+        self.value_width
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW_SIZE)
+    }
+}
 
 pub struct Chunker<'a> {
     buf: &'a [u8],
@@ -9,8 +49,9 @@ pub struct Chunker<'a> {
     pos: usize,
     chunk_start: usize,
     sizes: SizeParams,
-    max_value: u8,
+    max_value: u64,
     window_size: usize,
+    value_width: ValueWidth,
 }
 impl<'a> Chunker<'a> {
     pub fn default_sizes() -> SizeParams {
@@ -22,6 +63,16 @@ impl<'a> Chunker<'a> {
     }
 
     pub fn new(buf: &'a [u8], sizes: SizeParams, window_size: usize) -> Self {
+        Self::with_value_width(buf, sizes, window_size, ValueWidth::U8)
+    }
+
+    /// Creates a new chunker that compares values of the given width instead of single bytes.
+    pub fn with_value_width(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        window_size: usize,
+        value_width: ValueWidth,
+    ) -> Self {
         Chunker {
             buf,
             len: buf.len(),
@@ -30,9 +81,52 @@ impl<'a> Chunker<'a> {
             sizes,
             max_value: 0,
             window_size,
+            value_width,
+        }
+    }
+
+    /// Creates a new chunker if `sizes` and `window_size` can be used by the algorithm.
+    pub fn try_new(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        window_size: usize,
+    ) -> Result<Self, ChunkerError> {
+        Self::try_with_value_width(buf, sizes, window_size, ValueWidth::U8)
+    }
+
+    /// Creates a new chunker if the parameters can be used by the algorithm.
+    pub fn try_with_value_width(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        window_size: usize,
+        value_width: ValueWidth,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(
+            sizes,
+            &Config::new(window_size).with_value_width(value_width),
+        )?;
+        Ok(Self::with_value_width(buf, sizes, window_size, value_width))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            len: buf.len(),
+            pos: 0,
+            chunk_start: 0,
+            sizes: self.sizes,
+            max_value: self.max_value,
+            window_size: self.window_size,
+            value_width: self.value_width,
         }
     }
 
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + 2 * self.value_width.bytes()
+    }
+
     fn find_border(&mut self) -> Option<usize> {
         if self.len == self.pos {
             return None;
@@ -43,8 +137,10 @@ impl<'a> Chunker<'a> {
             return Some(self.pos);
         }
 
-        self.pos += 1;
-        self.max_value = self.buf[self.pos];
+        // values and borders stay on multiples of the width from the chunk start
+        let width = self.value_width.bytes();
+        self.pos += width;
+        self.max_value = self.value_width.read(self.buf, self.pos);
 
         while self.pos < self.len {
             let len = self.pos - self.chunk_start;
@@ -53,17 +149,19 @@ impl<'a> Chunker<'a> {
                 return Some(self.pos);
             }
 
-            if self.buf[self.pos] >= self.max_value {
+            let value = self.value_width.read(self.buf, self.pos);
+            if value >= self.max_value {
                 if len > self.window_size {
                     return Some(self.pos);
                 }
 
-                self.max_value = self.buf[self.pos];
+                self.max_value = value;
             }
 
-            self.pos += 1;
+            self.pos += width;
         }
 
+        self.pos = self.len;
         Some(self.pos)
     }
 }
@@ -81,3 +179,65 @@ impl Iterator for Chunker<'_> {
         Some(Chunk::new(start, length))
     }
 }
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_value_width(buf, sizes, config.window_size(), config.value_width())
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        // the byte after the chunk start is read before the search begins
+        sizes.check_with_min(2)?;
+
+        if config.window_size() == 0 || config.window_size() >= sizes.max {
+            return Err(ChunkerError::InvalidParameter {
+                name: "window_size",
+                reason: "has to be positive and less than max chunk size",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ram::Chunker;
+    use crate::{SizeParams, ValueWidth};
+
+    #[test]
+    fn wide_values_spread_borders_on_low_entropy_data() {
+        let data = (0..1024 * 1024)
+            .map(|_| b"ACGT"[rand::random::<usize>() % 4])
+            .collect::<Vec<_>>();
+        let sizes = SizeParams::new(2, 1024, 65536);
+        let window_size = 512;
+        let lengths = |value_width| {
+            Chunker::with_value_width(&data, sizes, window_size, value_width)
+                .map(|chunk| chunk.len)
+                .collect::<Vec<_>>()
+        };
+
+        // the maximum byte of the window is found again right after it
+        let narrow = lengths(ValueWidth::U8);
+        assert!(narrow.iter().all(|&len| len <= window_size + 64));
+
+        let wide = lengths(ValueWidth::U64);
+        let average = data.len() / wide.len();
+        assert!(average > window_size * 3 / 2, "{}", average);
+        assert!(wide.iter().any(|&len| len > window_size * 3));
+    }
+}
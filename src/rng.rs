@@ -0,0 +1,75 @@
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use sha3::{Shake256, Shake256Reader};
+
+/// Small deterministic generator used to derive tables from seeds.
+///
+/// SplitMix64 is used instead of `rand` generators, so that
+/// the same seed gives the same tables regardless of dependency versions.
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number uniformly distributed in `[0, 1)`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a normally distributed number with zero mean and unit variance,
+    /// calculated using the Box-Muller transform.
+    pub(crate) fn next_normal(&mut self) -> f64 {
+        let radius = (-2.0 * (1.0 - self.next_f64()).ln()).sqrt();
+        radius * (std::f64::consts::TAU * self.next_f64()).cos()
+    }
+}
+
+/// Stream of bytes derived from a secret key with SHAKE256, used to derive keyed tables.
+///
+/// The label of the table is hashed before the key, so that tables derived from one key
+/// do not share bytes, and the key can not be recovered from any of them.
+pub(crate) struct KeyStream {
+    reader: Shake256Reader,
+}
+
+impl KeyStream {
+    pub(crate) fn new(label: &[u8], key: &[u8; 32]) -> Self {
+        let mut hasher = Shake256::default();
+        hasher.update(label);
+        hasher.update(key);
+        Self {
+            reader: hasher.finalize_xof(),
+        }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.reader.read(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Shuffles all byte values, used to derive keyed tables from byte-indexed ones.
+    pub(crate) fn permutation(&mut self) -> [u8; 256] {
+        let mut permutation = [0u8; 256];
+        for (index, entry) in permutation.iter_mut().enumerate() {
+            *entry = index as u8;
+        }
+
+        for index in (1..256).rev() {
+            let other = (self.next_u64() % (index as u64 + 1)) as usize;
+            permutation.swap(index, other);
+        }
+        permutation
+    }
+}
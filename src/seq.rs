@@ -1,18 +1,32 @@
-use crate::{Chunk, SizeParams};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams, ValueWidth};
 use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum OperationMode {
     Increasing,
     Decreasing,
+    /// Chooses the direction that has more complete sequences in the `max` bytes after a chunk start,
+    /// so that data with skewed slopes does not end up cut mostly at max size.
+    ///
+    /// The direction is sampled at the first chunk and again at the first chunk
+    /// after every `SAMPLE_INTERVAL` max sizes of data.
+    Adaptive,
 }
 
-/// Contains parameters specified in the SeqCDC paper.
+/// Amount of max sizes that adaptive mode chunks before sampling the direction again.
+const SAMPLE_INTERVAL: usize = 16;
+
+/// Contains parameters specified in the SeqCDC paper, along with the operation mode,
+/// which is increasing by default, and the width of compared values, which are single bytes by default.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Config {
+    mode: OperationMode,
     sequence_length: usize,
     skip_trigger: usize,
     skip_size: usize,
+    value_width: ValueWidth,
 }
 
 pub struct Chunker<'a> {
@@ -21,28 +35,140 @@ pub struct Chunker<'a> {
     position: usize,
     sizes: SizeParams,
     mode: OperationMode,
+    direction: OperationMode,
+    since_sample: usize,
     sequence_length: usize,
     skip_trigger: usize,
     skip_size: usize,
+    value_width: ValueWidth,
 }
 
 impl Config {
     pub fn new(sequence_length: usize, skip_trigger: usize, skip_size: usize) -> Self {
         Self {
+            mode: OperationMode::Increasing,
             sequence_length,
             skip_trigger,
             skip_size,
+            value_width: ValueWidth::U8,
+        }
+    }
+
+    /// Returns the config that searches for sequences in the given mode.
+    pub fn with_mode(self, mode: OperationMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Returns the config that compares values of the given width instead of single bytes.
+    pub fn with_value_width(self, value_width: ValueWidth) -> Self {
+        Self {
+            value_width,
+            ..self
+        }
+    }
+
+    pub fn mode(&self) -> OperationMode {
+        self.mode
+    }
+
+    pub fn sequence_length(&self) -> usize {
+        self.sequence_length
+    }
+
+    pub fn skip_trigger(&self) -> usize {
+        self.skip_trigger
+    }
+
+    pub fn skip_size(&self) -> usize {
+        self.skip_size
+    }
+
+    pub fn value_width(&self) -> ValueWidth {
+        self.value_width
+    }
+
+    /// Checks the parameters that do not depend on sizes.
+    fn check(&self) -> Result<(), ChunkerError> {
+        if self.sequence_length == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "sequence_length",
+                reason: "has to be positive",
+            });
+        }
+        if self.skip_trigger == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "skip_trigger",
+                reason: "has to be positive",
+            });
         }
+        Ok(())
+    }
+}
+
+/// Formats the config as `sequence_length,skip_trigger,skip_size,value_bytes`, e.g. `5,55,256,1`.
+/// The operation mode is not included.
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.sequence_length,
+            self.skip_trigger,
+            self.skip_size,
+            self.value_width.bytes()
+        )
+    }
+}
+
+/// Parses the config written by `Display`, the value width can be omitted for single bytes.
+/// The operation mode is increasing.
+impl FromStr for Config {
+    type Err = ChunkerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ChunkerError::InvalidParameter {
+                name: "config",
+                reason: "has to be a list of non-negative integers",
+            })?;
+
+        let (sequence_length, skip_trigger, skip_size, value_bytes) = match values[..] {
+            [length, trigger, skip] => (length, trigger, skip, 1),
+            [length, trigger, skip, bytes] => (length, trigger, skip, bytes),
+            _ => {
+                return Err(ChunkerError::InvalidParameter {
+                    name: "config",
+                    reason: "has to contain three or four values",
+                })
+            }
+        };
+
+        let value_width = match value_bytes {
+            1 => ValueWidth::U8,
+            2 => ValueWidth::U16,
+            4 => ValueWidth::U32,
+            8 => ValueWidth::U64,
+            _ => {
+                return Err(ChunkerError::InvalidParameter {
+                    name: "value_width",
+                    reason: "has to be 1, 2, 4 or 8 bytes",
+                })
+            }
+        };
+
+        let config =
+            Config::new(sequence_length, skip_trigger, skip_size).with_value_width(value_width);
+        config.check()?;
+        Ok(config)
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self {
-            sequence_length: 5,
-            skip_trigger: 55,
-            skip_size: 256,
-        }
+        Self::new(5, 55, 256)
     }
 }
 
@@ -55,19 +181,54 @@ impl<'a> Chunker<'a> {
         }
     }
 
-    pub fn new(buf: &'a [u8], params: SizeParams, mode: OperationMode, config: Config) -> Self {
+    pub fn new(buf: &'a [u8], params: SizeParams, config: Config) -> Self {
         Self {
             buf,
             len: buf.len(),
             position: 0,
             sizes: params,
-            mode,
+            mode: config.mode,
+            direction: config.mode,
+            since_sample: usize::MAX,
             sequence_length: config.sequence_length,
             skip_trigger: config.skip_trigger,
             skip_size: config.skip_size,
+            value_width: config.value_width,
         }
     }
 
+    /// Creates a new chunker if `params` and `config` can be used by the algorithm.
+    pub fn try_new(
+        buf: &'a [u8],
+        params: SizeParams,
+        config: Config,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(params, &config)?;
+        Ok(Self::new(buf, params, config))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            len: buf.len(),
+            position: 0,
+            sizes: self.sizes,
+            mode: self.mode,
+            direction: self.direction,
+            since_sample: self.since_sample,
+            sequence_length: self.sequence_length,
+            skip_trigger: self.skip_trigger,
+            skip_size: self.skip_size,
+            value_width: self.value_width,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + self.skip_size + 2 * self.value_width.bytes()
+    }
+
     fn find_border_increasing(&mut self) -> Option<usize> {
         if self.position == self.len {
             return None;
@@ -79,17 +240,21 @@ impl<'a> Chunker<'a> {
             return Some(delta);
         }
 
-        self.position += self.sizes.min;
+        // values and borders stay on multiples of the width from the chunk start
+        let width = self.value_width.bytes();
+        let min = self.sizes.min.next_multiple_of(width);
+        let skip_size = self.skip_size.next_multiple_of(width);
+        self.position += min;
 
-        let mut chunk_len = self.sizes.min;
+        let mut chunk_len = min;
         let mut sequence_length = 0;
         let mut opposing_slope_count = 0;
 
-        while self.position < self.len && chunk_len < self.sizes.max {
-            self.position += 1;
-            chunk_len += 1;
+        while self.position < self.len && chunk_len + width <= self.sizes.max {
+            self.position += width;
+            chunk_len += width;
 
-            match self.buf[self.position - 1].cmp(&self.buf[self.position - 2]) {
+            match self.slope() {
                 Ordering::Less => {
                     sequence_length = 0;
                     opposing_slope_count += 1;
@@ -102,8 +267,8 @@ impl<'a> Chunker<'a> {
                 return Some(chunk_len);
             }
             if opposing_slope_count == self.skip_trigger {
-                self.position += self.skip_size;
-                chunk_len += self.skip_size;
+                self.position += skip_size;
+                chunk_len += skip_size;
                 opposing_slope_count = 0;
             }
         }
@@ -128,17 +293,21 @@ impl<'a> Chunker<'a> {
             return Some(delta);
         }
 
-        self.position += self.sizes.min;
+        // values and borders stay on multiples of the width from the chunk start
+        let width = self.value_width.bytes();
+        let min = self.sizes.min.next_multiple_of(width);
+        let skip_size = self.skip_size.next_multiple_of(width);
+        self.position += min;
 
-        let mut chunk_len = self.sizes.min;
+        let mut chunk_len = min;
         let mut sequence_length = 0;
         let mut opposing_slope_count = 0;
 
-        while self.position < self.len && chunk_len < self.sizes.max {
-            self.position += 1;
-            chunk_len += 1;
+        while self.position < self.len && chunk_len + width <= self.sizes.max {
+            self.position += width;
+            chunk_len += width;
 
-            match self.buf[self.position - 1].cmp(&self.buf[self.position - 2]) {
+            match self.slope() {
                 Ordering::Less => sequence_length += 1,
                 Ordering::Equal => continue,
                 Ordering::Greater => {
@@ -151,8 +320,8 @@ impl<'a> Chunker<'a> {
                 return Some(chunk_len);
             }
             if opposing_slope_count == self.skip_trigger {
-                self.position += self.skip_size;
-                chunk_len += self.skip_size;
+                self.position += skip_size;
+                chunk_len += skip_size;
                 opposing_slope_count = 0;
             }
         }
@@ -166,13 +335,70 @@ impl<'a> Chunker<'a> {
         Some(chunk_len)
     }
 
+    /// Compares the last value read, which ends at the position, with the one before it.
+    fn slope(&self) -> Ordering {
+        let width = self.value_width.bytes();
+        let value = self.value_width.read(self.buf, self.position - width);
+        value.cmp(&self.value_width.read(self.buf, self.position - 2 * width))
+    }
+
     /// Returns next size of the chunk.
     ///
     /// Reads the info about operation mode from the chunker instance.
     fn find_border(&mut self) -> Option<usize> {
-        match self.mode {
+        if self.mode == OperationMode::Adaptive
+            && self.since_sample >= SAMPLE_INTERVAL.saturating_mul(self.sizes.max)
+            && self.position < self.len
+        {
+            self.direction = self.sample_mode();
+            self.since_sample = 0;
+        }
+
+        let length = match self.direction {
             OperationMode::Increasing => self.find_border_increasing(),
-            OperationMode::Decreasing => self.find_border_decreasing(),
+            _ => self.find_border_decreasing(),
+        }?;
+        self.since_sample = self.since_sample.saturating_add(length);
+        Some(length)
+    }
+
+    /// Chooses the direction that has more complete sequences in the next `max` bytes.
+    ///
+    /// The sample always fits into the lookahead, so stream chunkers choose the same direction.
+    fn sample_mode(&self) -> OperationMode {
+        let end = self.len.min(self.position + self.sizes.max);
+        let width = self.value_width.bytes();
+
+        let (mut increasing, mut decreasing) = (0, 0);
+        let (mut increasing_length, mut decreasing_length) = (0, 0);
+        for pos in (self.position + width..end).step_by(width) {
+            let value = self.value_width.read(self.buf, pos);
+            match value.cmp(&self.value_width.read(self.buf, pos - width)) {
+                Ordering::Less => {
+                    increasing_length = 0;
+                    decreasing_length += 1;
+                }
+                Ordering::Equal => continue,
+                Ordering::Greater => {
+                    increasing_length += 1;
+                    decreasing_length = 0;
+                }
+            }
+
+            if increasing_length == self.sequence_length {
+                increasing += 1;
+                increasing_length = 0;
+            }
+            if decreasing_length == self.sequence_length {
+                decreasing += 1;
+                decreasing_length = 0;
+            }
+        }
+
+        if decreasing > increasing {
+            OperationMode::Decreasing
+        } else {
+            OperationMode::Increasing
         }
     }
 }
@@ -186,3 +412,130 @@ impl Iterator for Chunker<'_> {
         self.find_border().map(|length| Chunk::new(start, length))
     }
 }
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::new(buf, sizes, config)
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        // slope is calculated using the byte before the one after min size
+        sizes.check_with_min(1)?;
+        config.check()
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::seq::{Chunker, Config, OperationMode, SAMPLE_INTERVAL};
+    use crate::{Chunk, ChunkerError, ValueWidth};
+
+    #[test]
+    fn wide_values_find_sequences_in_low_entropy_data() {
+        let data = (0..1024 * 1024)
+            .map(|_| b"ACGT"[rand::random::<usize>() % 4])
+            .collect::<Vec<_>>();
+        let sizes = Chunker::default_sizes();
+        let lengths = |value_width| {
+            let config = Config::default().with_value_width(value_width);
+            Chunker::new(&data, sizes, config)
+                .map(|chunk| chunk.len)
+                .collect::<Vec<_>>()
+        };
+
+        // four distinct bytes can not make a sequence of five increasing ones
+        let narrow = lengths(ValueWidth::U8);
+        let (_, rest) = narrow.split_last().unwrap();
+        assert!(rest.iter().all(|&len| len >= sizes.max));
+
+        // a value spans eight bytes, so there are fewer of them to find a sequence in
+        let wide = lengths(ValueWidth::U64);
+        let below_max = wide.iter().filter(|&&len| len < sizes.max).count();
+        assert!(below_max > wide.len() / 2, "{} {}", below_max, wide.len());
+        assert!(wide.iter().all(|&len| len <= sizes.max + 256));
+    }
+
+    #[test]
+    fn adaptive_mode_follows_skewed_slopes() {
+        // values mostly go down, jumping up once in a while
+        let mut value = 0u8;
+        let falling = (0..1024 * 1024)
+            .map(|_| {
+                value = match rand::random::<u8>() % 8 {
+                    0 => value.wrapping_add(rand::random::<u8>() % 64),
+                    _ => value.wrapping_sub(1 + rand::random::<u8>() % 4),
+                };
+                value
+            })
+            .collect::<Vec<_>>();
+        let rising = falling.iter().map(|byte| !byte).collect::<Vec<_>>();
+
+        let sizes = Chunker::default_sizes();
+        let chunks = |data, mode| {
+            Chunker::new(data, sizes, Config::default().with_mode(mode)).collect::<Vec<_>>()
+        };
+
+        let decreasing = chunks(&falling, OperationMode::Decreasing);
+        let increasing = chunks(&falling, OperationMode::Increasing);
+        assert!(decreasing.len() > increasing.len() * 3 / 2);
+        assert_eq!(chunks(&falling, OperationMode::Adaptive), decreasing);
+
+        let increasing = chunks(&rising, OperationMode::Increasing);
+        assert_eq!(chunks(&rising, OperationMode::Adaptive), increasing);
+
+        // slope changes halfway through, so the direction sampled first does not fit the rest
+        let changing = [&rising[..], &falling[..]].concat();
+        let increasing = chunks(&changing, OperationMode::Increasing);
+        let decreasing = chunks(&changing, OperationMode::Decreasing);
+        let adaptive = chunks(&changing, OperationMode::Adaptive);
+        assert!(adaptive.len() > increasing.len().max(decreasing.len()) * 11 / 10);
+
+        // last direction is sampled from falling data, so the tail is cut as often as by decreasing mode
+        let tail = Chunker::default_sizes().max * SAMPLE_INTERVAL * 2;
+        let count_tail = |chunks: &[Chunk]| {
+            chunks
+                .iter()
+                .filter(|chunk| chunk.pos >= changing.len() - tail)
+                .count()
+        };
+        assert!(count_tail(&adaptive) >= count_tail(&decreasing) * 9 / 10);
+        assert!(count_tail(&adaptive) > count_tail(&increasing) * 3 / 2);
+    }
+
+    #[test]
+    fn config_is_formatted_and_parsed() {
+        let config = Config::new(3, 20, 128).with_value_width(ValueWidth::U32);
+        assert_eq!(config.to_string(), "3,20,128,4");
+        assert_eq!("3,20,128,4".parse::<Config>(), Ok(config));
+        assert_eq!(config.sequence_length(), 3);
+        assert_eq!(config.skip_trigger(), 20);
+        assert_eq!(config.skip_size(), 128);
+        assert_eq!(config.value_width(), ValueWidth::U32);
+
+        assert_eq!(Config::default().to_string().parse(), Ok(Config::default()));
+        assert_eq!("5, 55, 256".parse(), Ok(Config::default()));
+        assert!("5,55".parse::<Config>().is_err());
+        assert!("5,55,256,3".parse::<Config>().is_err());
+        assert!(matches!(
+            "0,55,256".parse::<Config>(),
+            Err(ChunkerError::InvalidParameter {
+                name: "sequence_length",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{Algorithm, AnyChunker, SizeParams};
+use std::io::{self, ErrorKind, Read};
+
+/// Chunk that owns its data, produced by chunking a stream.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OwnedChunk {
+    pub pos: usize,
+    pub data: Vec<u8>,
+}
+
+/// Chunker that reads data from any `Read` source.
+///
+/// Keeps only as much data in memory as the algorithm needs to find the next border,
+/// which is at most `SizeParams::max` plus the window overhead of the algorithm.
+/// Produces the same chunks as the slice-based chunker of the same algorithm.
+pub struct Chunker<R> {
+    reader: R,
+    buf: StreamBuffer,
+}
+
+/// Buffer holding the data of the stream starting at the beginning of the next chunk.
+pub(crate) struct StreamBuffer {
+    chunker: Option<AnyChunker<'static>>,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    /// Creates a chunker of the given algorithm with default algorithm-specific parameters.
+    pub fn new(reader: R, algorithm: Algorithm, sizes: SizeParams) -> Self {
+        Self::with_chunker(reader, AnyChunker::new(algorithm, &[], sizes))
+    }
+
+    /// Creates a chunker that uses the algorithm and parameters of `chunker`.
+    ///
+    /// `chunker` is expected to be created over an empty buffer, e.g.
+    /// `ultra::Chunker::new(&[], sizes).into()`.
+    pub fn with_chunker(reader: R, chunker: AnyChunker<'static>) -> Self {
+        Self {
+            reader,
+            buf: StreamBuffer::new(chunker),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads data until the buffer contains enough bytes to find the next border
+    /// or the end of the stream is reached.
+    fn fill_buf(&mut self) -> io::Result<()> {
+        while self.buf.needs_data() {
+            match self.reader.read(self.buf.spare()) {
+                Ok(read) => self.buf.advance(read),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<OwnedChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill_buf() {
+            return Some(Err(e));
+        }
+
+        self.buf.next_chunk().map(Ok)
+    }
+}
+
+impl StreamBuffer {
+    pub(crate) fn new(chunker: AnyChunker<'static>) -> Self {
+        let lookahead = chunker.lookahead();
+        Self {
+            chunker: Some(chunker),
+            buf: vec![0; lookahead],
+            start: 0,
+            end: 0,
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Checks if more data has to be read before the next chunk can be found.
+    pub(crate) fn needs_data(&self) -> bool {
+        !self.eof && self.end - self.start < self.buf.len()
+    }
+
+    /// Returns the free part of the buffer, moving unprocessed data to its beginning.
+    pub(crate) fn spare(&mut self) -> &mut [u8] {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+
+        &mut self.buf[self.end..]
+    }
+
+    /// Marks `read` bytes of the free part as filled. Zero means the end of the stream.
+    pub(crate) fn advance(&mut self, read: usize) {
+        if read == 0 {
+            self.eof = true;
+        }
+        self.end += read;
+    }
+
+    /// Cuts the next chunk from the buffer.
+    ///
+    /// Expects that the buffer does not need data.
+    pub(crate) fn next_chunk(&mut self) -> Option<OwnedChunk> {
+        if self.start == self.end {
+            return None;
+        }
+
+        let (chunker, length) = self
+            .chunker
+            .take()?
+            .find_in(&self.buf[self.start..self.end]);
+        self.chunker = Some(chunker);
+        let length = length?;
+
+        let chunk = OwnedChunk {
+            pos: self.pos,
+            data: self.buf[self.start..self.start + length].to_vec(),
+        };
+        self.start += length;
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::{Chunker, OwnedChunk};
+    use crate::{generate_data, seq, Algorithm, AnyChunker, Chunk, SizeParams, ValueWidth};
+    use std::io::Read;
+
+    /// Reader that gives out at most `step` bytes at a time.
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.step.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    fn generate_repeating_data(size: usize) -> Vec<u8> {
+        let block = generate_data(50_000);
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            data.extend_from_slice(&block);
+            data.extend_from_slice(&generate_data(1000));
+        }
+        data.truncate(size);
+        data
+    }
+
+    fn assert_same_chunks(data: &[u8], stream_chunks: Vec<OwnedChunk>, chunks: Vec<Chunk>) {
+        assert_eq!(stream_chunks.len(), chunks.len());
+        for (owned, chunk) in stream_chunks.iter().zip(chunks) {
+            assert_eq!(owned.pos, chunk.pos);
+            assert_eq!(owned.data, &data[chunk.pos..chunk.pos + chunk.len]);
+        }
+    }
+
+    #[test]
+    fn stream_matches_slice_chunkers() {
+        for data in [
+            generate_data(1024 * 1024),
+            generate_repeating_data(1024 * 1024),
+        ] {
+            for algorithm in Algorithm::ALL {
+                let sizes = algorithm.default_sizes();
+                let reader = SlowReader {
+                    data: &data,
+                    step: 3000,
+                };
+
+                let stream_chunks = Chunker::new(reader, algorithm, sizes)
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .unwrap();
+                let chunks = AnyChunker::new(algorithm, &data, sizes).collect();
+
+                assert_same_chunks(&data, stream_chunks, chunks);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_matches_slice_chunkers_on_short_data() {
+        let data = generate_data(20_000);
+
+        for algorithm in Algorithm::ALL {
+            for length in [0, 1, 100, 2048, 4096, 8192, 10_000, 20_000] {
+                let data = &data[..length];
+                let sizes = SizeParams::new(2048, 4096, 8192);
+
+                let stream_chunks = Chunker::new(data, algorithm, sizes)
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .unwrap();
+                let chunks = AnyChunker::new(algorithm, data, sizes).collect();
+
+                assert_same_chunks(data, stream_chunks, chunks);
+            }
+        }
+    }
+
+    #[test]
+    fn stream_uses_custom_chunker() {
+        let data = generate_data(512 * 1024);
+        let sizes = SizeParams::new(2048, 4096, 8192);
+        let config = seq::Config::new(3, 20, 128).with_value_width(ValueWidth::U64);
+
+        // adaptive mode samples the direction again several times within the data
+        for mode in [seq::OperationMode::Decreasing, seq::OperationMode::Adaptive] {
+            let chunker = seq::Chunker::new(&[], sizes, config.with_mode(mode));
+            let stream_chunks = Chunker::with_chunker(data.as_slice(), chunker.into())
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap();
+            let chunks = seq::Chunker::new(&data, sizes, config.with_mode(mode)).collect();
+
+            assert_same_chunks(&data, stream_chunks, chunks);
+        }
+    }
+}
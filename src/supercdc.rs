@@ -1,28 +1,299 @@
-use crate::{Chunk, SizeParams};
+use crate::fastcdc::Normalization;
+use crate::rng::KeyStream;
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read, Write};
 
 const MIN_CHUNK_SIZE: usize = 1024 * 4;
 const AVG_CHUNK_SIZE: usize = 1024 * 8;
 const MAX_CHUNK_SIZE: usize = 1024 * 64;
 
-// 8KB, 4KB and 2KB masks
-const MASK_S: u64 = 0b1111_1111_1111;
-const MASK_L: u64 = 0b111_1111_1111;
-const MASK_B: u64 = 0b11_1111_1111;
+/// Capacity of `BoundedRecords::default`.
+const DEFAULT_CAPACITY: usize = 1 << 16;
 
-const MASK_S_LS: u64 = MASK_B << 1;
-const MASK_L_LS: u64 = MASK_L << 1;
-const MASK_B_LS: u64 = MASK_B << 1;
+/// Signature and version at the beginning of saved records.
+const RECORDS_MAGIC: &[u8; 4] = b"SCR1";
 
-pub struct Chunker<'a> {
+/// Storage of chunk lengths remembered by the hashes at the end of preceding chunks.
+pub trait RecordStore {
+    /// Returns the length remembered for `hash`.
+    fn get(&mut self, hash: u64) -> Option<usize>;
+
+    /// Remembers `length` for `hash`.
+    fn insert(&mut self, hash: u64, length: usize);
+
+    /// Returns all records, starting with the ones that would be evicted first.
+    fn records(&self) -> Vec<(u64, usize)>;
+}
+
+impl RecordStore for HashMap<u64, usize> {
+    fn get(&mut self, hash: u64) -> Option<usize> {
+        HashMap::get(self, &hash).copied()
+    }
+
+    fn insert(&mut self, hash: u64, length: usize) {
+        HashMap::insert(self, hash, length);
+    }
+
+    fn records(&self) -> Vec<(u64, usize)> {
+        self.iter().map(|(&hash, &length)| (hash, length)).collect()
+    }
+}
+
+/// Record store that remembers nothing, so that every border is found by the Gear hash
+/// and chunks do not depend on the previously chunked data.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct NoRecords;
+
+impl RecordStore for NoRecords {
+    fn get(&mut self, _hash: u64) -> Option<usize> {
+        None
+    }
+
+    fn insert(&mut self, _hash: u64, _length: usize) {}
+
+    fn records(&self) -> Vec<(u64, usize)> {
+        vec![]
+    }
+}
+
+/// Record that is evicted first when `BoundedRecords` is full.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Eviction {
+    LeastRecentlyUsed,
+    /// Ties are broken by evicting the least recently used record.
+    LeastFrequentlyUsed,
+}
+
+/// Record store that keeps at most `capacity` records.
+#[derive(Clone, Debug)]
+pub struct BoundedRecords {
+    capacity: usize,
+    eviction: Eviction,
+    entries: HashMap<u64, Entry>,
+    // (priority, last use, hash), lowest one is evicted first
+    order: BTreeSet<(u64, u64, u64)>,
+    clock: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    length: usize,
+    uses: u64,
+    last_use: u64,
+}
+
+impl BoundedRecords {
+    pub fn new(capacity: usize, eviction: Eviction) -> Self {
+        Self {
+            capacity,
+            eviction,
+            entries: HashMap::new(),
+            order: BTreeSet::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn order_key(&self, hash: u64, entry: Entry) -> (u64, u64, u64) {
+        match self.eviction {
+            Eviction::LeastRecentlyUsed => (0, entry.last_use, hash),
+            Eviction::LeastFrequentlyUsed => (entry.uses, entry.last_use, hash),
+        }
+    }
+
+    /// Marks the record of `hash` as used and returns its updated entry.
+    fn touch(&mut self, hash: u64) -> Option<&mut Entry> {
+        let entry = *self.entries.get(&hash)?;
+        self.order.remove(&self.order_key(hash, entry));
+
+        self.clock += 1;
+        let used = Entry {
+            uses: entry.uses + 1,
+            last_use: self.clock,
+            ..entry
+        };
+        self.order.insert(self.order_key(hash, used));
+
+        let entry = self.entries.get_mut(&hash)?;
+        *entry = used;
+        Some(entry)
+    }
+}
+
+impl Default for BoundedRecords {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, Eviction::LeastRecentlyUsed)
+    }
+}
+
+impl RecordStore for BoundedRecords {
+    fn get(&mut self, hash: u64) -> Option<usize> {
+        self.touch(hash).map(|entry| entry.length)
+    }
+
+    fn insert(&mut self, hash: u64, length: usize) {
+        if let Some(entry) = self.touch(hash) {
+            entry.length = length;
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            if let Some((_, _, evicted)) = self.order.pop_first() {
+                self.entries.remove(&evicted);
+            }
+        }
+
+        self.clock += 1;
+        let entry = Entry {
+            length,
+            uses: 1,
+            last_use: self.clock,
+        };
+        self.order.insert(self.order_key(hash, entry));
+        self.entries.insert(hash, entry);
+    }
+
+    fn records(&self) -> Vec<(u64, usize)> {
+        self.order
+            .iter()
+            .map(|&(_, _, hash)| (hash, self.entries[&hash].length))
+            .collect()
+    }
+}
+
+/// Writes the records to `writer`, so that they can be reused when chunking the same data later.
+///
+/// Each record takes 12 bytes: the hash and the length as little-endian `u64` and `u32`.
+pub fn save_records(records: &impl RecordStore, mut writer: impl Write) -> io::Result<()> {
+    let records = records.records();
+
+    writer.write_all(RECORDS_MAGIC)?;
+    writer.write_all(&(records.len() as u64).to_le_bytes())?;
+    for (hash, length) in records {
+        let length = u32::try_from(length)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "length exceeds u32"))?;
+        writer.write_all(&hash.to_le_bytes())?;
+        writer.write_all(&length.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Reads records written by `save_records` into `records`, keeping their eviction order.
+///
+/// Records of chunks that do not fit `sizes`, which the chunker is going to use, are rejected.
+pub fn load_records(
+    mut reader: impl Read,
+    sizes: SizeParams,
+    records: &mut impl RecordStore,
+) -> io::Result<()> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != RECORDS_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a SuperCDC records file",
+        ));
+    }
+
+    let mut count = [0; 8];
+    reader.read_exact(&mut count)?;
+    for _ in 0..u64::from_le_bytes(count) {
+        let mut record = [0; 12];
+        reader.read_exact(&mut record)?;
+
+        let (hash, length) = record.split_at(8);
+        let hash = u64::from_le_bytes(hash.try_into().unwrap());
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        if !fits(length, sizes) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "record length is outside of min and max sizes",
+            ));
+        }
+        records.insert(hash, length);
+    }
+    Ok(())
+}
+
+/// Checks that a remembered chunk `length` can be cut with `sizes`.
+fn fits(length: usize, sizes: SizeParams) -> bool {
+    (sizes.min..=sizes.max).contains(&length)
+}
+
+/// Parameters of SuperCDC: the store of remembered chunk lengths, the normalization level
+/// and an optional secret key that the Gear table is derived from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config<S = HashMap<u64, usize>> {
+    records: S,
+    normalization: Normalization,
+    key: Option<[u8; 32]>,
+}
+
+impl<S> Config<S> {
+    pub fn new(records: S, normalization: Normalization) -> Self {
+        Self {
+            records,
+            normalization,
+            key: None,
+        }
+    }
+
+    /// Returns the config that derives the Gear table from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn records(&self) -> &S {
+        &self.records
+    }
+
+    pub fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+}
+
+impl<S: Default> Default for Config<S> {
+    fn default() -> Self {
+        Self::new(S::default(), Normalization::Level1)
+    }
+}
+
+pub struct Chunker<'a, S = HashMap<u64, usize>> {
     buf: &'a [u8],
-    records: HashMap<u64, usize>,
+    records: S,
     last_hash: u64,
     record_last_hash: bool,
     pos: usize,
     shelved: Option<usize>,
     sizes: SizeParams,
+    mask_s: u64,
+    mask_l: u64,
+    mask_b: u64,
+    gear: Box<[u64; 256]>,
+    gear_ls: Box<[u64; 256]>,
 }
 
 impl<'a> Chunker<'a> {
@@ -35,18 +306,57 @@ impl<'a> Chunker<'a> {
     }
 
     pub fn new(buf: &'a [u8]) -> Self {
-        Self {
-            buf,
-            pos: 0,
-            records: Default::default(),
-            last_hash: 0,
-            record_last_hash: false,
-            shelved: None,
-            sizes: Self::default_sizes(),
-        }
+        Self::with_records(buf, Default::default(), Self::default_sizes())
     }
 
     pub fn with_records(buf: &'a [u8], records: HashMap<u64, usize>, sizes: SizeParams) -> Self {
+        Self::with_key(buf, records, sizes, None)
+    }
+
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_with_records(
+        buf: &'a [u8],
+        records: HashMap<u64, usize>,
+        sizes: SizeParams,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Self::default_config())?;
+        Ok(Self::with_records(buf, records, sizes))
+    }
+}
+
+impl<'a> Chunker<'a, NoRecords> {
+    /// Creates a new chunker that does not skip data using remembered lengths,
+    /// so that the same data is always split into the same chunks.
+    pub fn without_records(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_key(buf, NoRecords, sizes, None)
+    }
+}
+
+impl<'a, S: RecordStore> Chunker<'a, S> {
+    /// Creates a new chunker that uses the Gear table derived from a secret `key` if it is given,
+    /// so that borders can not be predicted without the key.
+    ///
+    /// Records are bound to the table, so they can be reused only with the same key.
+    pub fn with_key(buf: &'a [u8], records: S, sizes: SizeParams, key: Option<&[u8; 32]>) -> Self {
+        Self::with_params(buf, records, sizes, Normalization::Level1, key)
+    }
+
+    /// Creates a new chunker with masks derived from `avg` at the given normalization level
+    /// and the Gear table derived from `key` if it is given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no masks for the average size at the given normalization level.
+    pub fn with_params(
+        buf: &'a [u8],
+        records: S,
+        sizes: SizeParams,
+        normalization: Normalization,
+        key: Option<&[u8; 32]>,
+    ) -> Self {
+        let (mask_s, mask_l, mask_b) =
+            masks(sizes, normalization).unwrap_or_else(|e| panic!("{}", e));
+        let gear = gear_table(key);
         Self {
             buf,
             records,
@@ -55,19 +365,23 @@ impl<'a> Chunker<'a> {
             pos: 0,
             shelved: None,
             sizes,
+            mask_s,
+            mask_l,
+            mask_b,
+            gear_ls: shifted_table(&gear),
+            gear,
         }
     }
 
     fn use_record_map(&mut self, hash: u64, length: usize) -> Option<usize> {
-        if self.record_last_hash {
+        // the last chunk of data can be shorter than min size
+        if self.record_last_hash && fits(length, self.sizes) {
             self.records.insert(self.last_hash, length);
         }
 
-        if let Some(&found_length) = self.records.get(&hash) {
+        if let Some(found_length) = self.records.get(hash) {
             self.record_last_hash = false;
-            if self.pos + found_length < self.buf.len() {
-                return Some(found_length);
-            }
+            return Some(found_length);
         } else {
             self.record_last_hash = true;
         }
@@ -76,6 +390,41 @@ impl<'a> Chunker<'a> {
         None
     }
 
+    /// Creates a new chunker if the parameters can be used by the algorithm.
+    pub fn try_with_params(
+        buf: &'a [u8],
+        records: S,
+        sizes: SizeParams,
+        normalization: Normalization,
+        key: Option<&[u8; 32]>,
+    ) -> Result<Self, ChunkerError> {
+        validate(sizes, normalization)?;
+        Ok(Self::with_params(buf, records, sizes, normalization, key))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_, S> {
+        Chunker {
+            buf,
+            records: self.records,
+            last_hash: self.last_hash,
+            record_last_hash: self.record_last_hash,
+            pos: 0,
+            shelved: self.shelved,
+            sizes: self.sizes,
+            mask_s: self.mask_s,
+            mask_l: self.mask_l,
+            mask_b: self.mask_b,
+            gear: self.gear,
+            gear_ls: self.gear_ls,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + 1
+    }
+
     fn find_border(&mut self, buf: &[u8]) -> Option<(u64, usize)> {
         if buf.is_empty() {
             return None;
@@ -101,20 +450,20 @@ impl<'a> Chunker<'a> {
 
         for index in 1..16 {
             fingerprint =
-                fingerprint.wrapping_add(GEAR[buf[self.sizes.min - index] as usize] << index);
+                fingerprint.wrapping_add(self.gear[buf[self.sizes.min - index] as usize] << index);
             pos += 1;
         }
 
         while pos < center / 2 {
             let a = pos * 2;
-            gear = GEAR_LS[buf[a] as usize];
+            gear = self.gear_ls[buf[a] as usize];
             fingerprint = (fingerprint << 2).wrapping_add(gear);
-            if fingerprint & MASK_S_LS == 0 {
+            if fingerprint & (self.mask_b << 1) == 0 {
                 return Some((gear, a));
             }
-            gear = GEAR[buf[a + 1] as usize];
+            gear = self.gear[buf[a + 1] as usize];
             fingerprint = fingerprint.wrapping_add(gear);
-            if fingerprint & MASK_S == 0 {
+            if fingerprint & self.mask_s == 0 {
                 return Some((gear, a + 1));
             }
             pos += 1;
@@ -122,23 +471,23 @@ impl<'a> Chunker<'a> {
 
         while pos < remaining / 2 {
             let a = pos * 2;
-            gear = GEAR_LS[buf[a] as usize];
+            gear = self.gear_ls[buf[a] as usize];
             fingerprint = (fingerprint << 2).wrapping_add(gear);
-            if fingerprint & MASK_L_LS == 0 {
+            if fingerprint & (self.mask_l << 1) == 0 {
                 return Some((gear, a));
             }
-            if !breakpoint_flag && fingerprint & MASK_B_LS == 0 {
+            if !breakpoint_flag && fingerprint & (self.mask_b << 1) == 0 {
                 breakpoint_flag = true;
                 breakpoint = a;
                 breakpoint_gear = gear;
             }
 
-            gear = GEAR[buf[a + 1] as usize];
+            gear = self.gear[buf[a + 1] as usize];
             fingerprint = fingerprint.wrapping_add(gear);
-            if fingerprint & MASK_L == 0 {
+            if fingerprint & self.mask_l == 0 {
                 return Some((gear, a + 1));
             }
-            if !breakpoint_flag && fingerprint & MASK_B == 0 {
+            if !breakpoint_flag && fingerprint & self.mask_b == 0 {
                 breakpoint_flag = true;
                 breakpoint = a + 1;
                 breakpoint_gear = gear;
@@ -153,21 +502,23 @@ impl<'a> Chunker<'a> {
         Some((breakpoint_gear, breakpoint))
     }
 
-    pub fn give_records(self) -> HashMap<u64, usize> {
+    pub fn give_records(self) -> S {
         self.records
     }
 }
 
-impl Iterator for Chunker<'_> {
+impl<S: RecordStore> Iterator for Chunker<'_, S> {
     type Item = Chunk;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(length) = self.shelved {
-            self.shelved = None;
-
-            let chunk = Chunk::new(self.pos, length);
-            self.pos += length;
-            return Some(chunk);
+        // remembered length is used only if it does not reach the end of the data
+        // and fits the sizes, which could have been different when it was recorded
+        if let Some(length) = self.shelved.take() {
+            if self.pos + length < self.buf.len() && fits(length, self.sizes) {
+                let chunk = Chunk::new(self.pos, length);
+                self.pos += length;
+                return Some(chunk);
+            }
         }
 
         let search_range = self.pos..self.buf.len();
@@ -187,9 +538,83 @@ impl Iterator for Chunker<'_> {
     }
 }
 
+impl<'a, S: RecordStore + Clone + Default> Chunking<'a> for Chunker<'a, S> {
+    type Config = Config<S>;
+
+    fn default_sizes() -> SizeParams {
+        <Chunker>::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        let key = config.key;
+        Chunker::with_params(
+            buf,
+            config.records,
+            sizes,
+            config.normalization,
+            key.as_ref(),
+        )
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        validate(sizes, config.normalization)
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+fn validate(sizes: SizeParams, normalization: Normalization) -> Result<(), ChunkerError> {
+    // fingerprint is initialized using 15 bytes before min size
+    sizes.check_with_min(16)?;
+    masks(sizes, normalization).map(|_| ())
+}
+
+/// Selects the small, large and backup masks for the average size.
+///
+/// Mask of `n` bits is used for chunks of `2^(n + 1)` bytes, so the small mask matches `avg`,
+/// and the large and backup ones are that many and one more bits shorter
+/// as the normalization level.
+fn masks(sizes: SizeParams, normalization: Normalization) -> Result<(u64, u64, u64), ChunkerError> {
+    let bits = (sizes.avg as f64).log2().round() as usize;
+    let backup_bits = bits.checked_sub(normalization.bits() + 2);
+
+    match backup_bits {
+        Some(backup_bits) if backup_bits > 0 => {
+            let mask = |bits: usize| (1u64 << bits) - 1;
+            Ok((mask(bits - 1), mask(backup_bits + 1), mask(backup_bits)))
+        }
+        _ => Err(ChunkerError::InvalidSizes {
+            sizes,
+            reason: "avg is not supported at this normalization level",
+        }),
+    }
+}
+
+/// Returns the Gear table derived from `key` with SHAKE256, or `GEAR` if there is no key.
+pub(crate) fn gear_table(key: Option<&[u8; 32]>) -> Box<[u64; 256]> {
+    match key {
+        Some(key) => {
+            let mut stream = KeyStream::new(b"gear", key);
+            Box::new([0; 256].map(|_: u64| stream.next_u64()))
+        }
+        None => Box::new(GEAR),
+    }
+}
+
+/// Returns `table` shifted one bit to the left, which is used to process two bytes at a time.
+pub(crate) fn shifted_table(table: &[u64; 256]) -> Box<[u64; 256]> {
+    Box::new(table.map(|entry| entry << 1))
+}
+
 // Gear table taken from https://github.com/nlfiedler/fastcdc-rs
 #[rustfmt::skip]
-const GEAR: [u64; 256] = [
+pub(crate) const GEAR: [u64; 256] = [
     0x3b5d3c7d207e37dc, 0x784d68ba91123086, 0xcd52880f882e7298, 0xeacf8e4e19fdcca7,
     0xc31f385dfbd1632b, 0x1d5f27001e25abe6, 0x83130bde3c9ad991, 0xc4b225676e9b7649,
     0xaa329b29e08eb499, 0xb67fcbd21e577d58, 0x0027baaada2acf6b, 0xe3ef2d5ac73c2226,
@@ -256,71 +681,152 @@ const GEAR: [u64; 256] = [
     0x8e3e4221d3614413, 0xef14d0d86bf1a22c, 0xe1d830d3f16c5ddb, 0xaabd2b2a451504e1
 ];
 
-// Gear table, shifted one bit to the left, taken from https://github.com/nlfiedler/fastcdc-rs
-#[rustfmt::skip]
-const GEAR_LS: [u64; 256] = [
-    0x76ba78fa40fc6fb8, 0xf09ad1752224610c, 0x9aa5101f105ce530, 0xd59f1c9c33fb994e,
-    0x863e70bbf7a2c656, 0x3abe4e003c4b57cc, 0x062617bc7935b322, 0x89644acedd36ec92,
-    0x54653653c11d6932, 0x6cff97a43caefab0, 0x004f7555b4559ed6, 0xc7de5ab58e78444c,
-    0x1121e49adda6256e, 0x5013c06d0a3af8fc, 0xe14dfcbc0027b036, 0x3a04c6088a59d828,
-    0x070c8c64c91c491e, 0x9b559e7b9b257368, 0xebc025cc7830f0ac, 0x10c5f3a70438016c,
-    0x505ee670ea1edf14, 0x3cb07b8d839616de, 0xf4628b6d2e874fe2, 0x57641fdc80900fd6,
-    0x629679fc0f7074ba, 0x73b84f1315b7341e, 0x6e07ebd23754c57c, 0x9e1770cd02befb30,
-    0x7b30cf883d53b9a6, 0x37c3f4ca8857e458, 0x28601b498aac63b2, 0xcd31d3978ca8b932,
-    0x8ec620fd8c9d254a, 0x8cb043f8cad2d448, 0xec32d80c9045e16e, 0x90b7d083e6a4bc02,
-    0xeca579390b2e9fea, 0x95b06a5f59d3c7d2, 0x54dda3b9d66bd31c, 0x8de90775b822d01e,
-    0x79fb182fd39e25e2, 0x137078bc5d4ac8e2, 0x5cccb9fa49c72552, 0xd86789ca0997122a,
-    0x7f7362bf93fce8a2, 0xaffa3fa328be432a, 0x635bc10a6887dfb0, 0x4abdf930c7c3c5a4,
-    0x21d56c011aac859e, 0x8de090c64af59008, 0x4a5b8854f1344fa6, 0xb555bf39cef5eaca,
-    0xd68f39aa7b0ffd76, 0xc613c5a7f27b6e5e, 0x8ae71ff7543ff3ac, 0xd7aafe7e4b5ef2d0,
-    0xcec0a90db21a1494, 0xc29a172cc77f7b5c, 0x6f77b1b02dd60828, 0xbdf149e2d66b422c,
-    0xcf265b0b555ffdac, 0x102c3975d219fa90, 0x0aaa0f7d6529e116, 0x22469d4dffa73364,
-    0x5ae19e96486be604, 0xa51352eacb785a4e, 0x1cab086fff9533bc, 0x2da4e096e22b8080,
-    0x1113779bf8cc1c82, 0xbc1a9ccfb924251a, 0xe553f122e0c7db2e, 0x8716d3a813c02dc6,
-    0xbe9fdb14bb14872e, 0x01e83b9e83a807ee, 0x9029d6071ca4c07e, 0x3b5f7598b1c5aca2,
-    0xfc5e8b1c97c2e15e, 0x88afd8829bed5280, 0x0dcc5e28a2246628, 0x7a2029a2e7752598,
-    0xbde631c4bdaaeec0, 0x3fd41bd3bf950a4a, 0x8b3bc3ced840c496, 0x5dd8312c2fc5accc,
-    0x24d4580d56b50796, 0x62642a646c1ec264, 0xca842a07b7680246, 0x5acf850fd4113566,
-    0xd9277feb4ad7ad6c, 0x9ff6406d956db31a, 0x9cf6f0b637cf5a9e, 0xdb685dec313fa2c6,
-    0xb920a510e07311ec, 0x6cbf383a58d23108, 0x8c80b06d01b337fc, 0x79a8c4980eb27d8c,
-    0xfe3d51b0baf8b00a, 0x029085a9016ae292, 0x16c93796b5050d10, 0x79aae11daf3631e0,
-    0xd30f90c5f7a5e5e0, 0x304e62ce3e19b058, 0x75e27d162db180c6, 0x1d4621397b2a3774,
-    0xa28208b7f670b95a, 0x559783415e3efa60, 0xcc889b13be077fbe, 0x43198ee370311ff2,
-    0x3068853b60387376, 0x4295ba0ffc10d43e, 0x1e0f83363ed67ff2, 0xad452f637e9ffcaa,
-    0x29aab1c9278a9f8e, 0x817f8498ec8aa596, 0x2634e0df150a4196, 0x64453a64526b7aa4,
-    0x4ac1a1ebb89fdf5e, 0x3b798906ab2d376c, 0x1fb038730b816ad4, 0xc0702fc2ac1e57b4,
-    0x83769f03b12565aa, 0x61890c9e9c51a5ae, 0x7d9893f3b3ad84c6, 0xa260fd336a574cbc,
-    0x15e56d11b5094ea4, 0xebae4a477236416c, 0xdb2bfe3fe8c6900c, 0xac5e42aaa8b06734,
-    0x819c8ff11266c68c, 0x90f047ca113681b0, 0xc8e4f8fd78db2b24, 0xb51ee4efd292e174,
-    0xf945e80d639176a0, 0xb63f152be2f220e0, 0xa6095f3f92050c0a, 0xa88156ff9261ce90,
-    0x625a4bf794556b42, 0x21e949646949aaea, 0x20603aaa08fce8e6, 0x76c6e510d8c2b23c,
-    0x5268056ef8889c0c, 0x8a3e6949a7d2fbae, 0x62b1b029b0378af6, 0x06928484b737b4d2,
-    0xc4065ff3ca65b376, 0xc55c0cd71642f3ca, 0x2a8bc2185f1ae3b0, 0xffee907d65a47f80,
-    0x0128bf9d7b931b0c, 0x0ec9777d3364d944, 0x363d8c4509817f86, 0xb1c1f989e1546c56,
-    0xbe957df50f1a8b1a, 0xfae9585f2c0f8a32, 0x49c7f66fbf197f52, 0x7ed2fc87958ae9ca,
-    0x0de2947ed1e99aa6, 0x49447a0ede39ca44, 0xce4b9b00910d1990, 0x7e78e53d648c86c4,
-    0xb1ed9aaf67983db0, 0xc653ca484aa82aee, 0xc554d115ab5c3580, 0x14484acc4d37f08a,
-    0x2d16348ea7594e96, 0xef135fdffe5cfc78, 0xd866c41276df99b6, 0x99d1ea17a5181364,
-    0x00d45b72b5d15526, 0x2eb61ac4787a3518, 0x30c0ba726a6718b6, 0xb76dec26d95a78e2,
-    0x1ea7440e03f1b14c, 0x5718b5a5cfd278ce, 0x816b58a24f595452, 0x18f7ec7840eb12be,
-    0xf17b3efc029500b8, 0x6593d3e9f3918064, 0xdfac09304fd723e6, 0x57c8b3e90582df7a,
-    0xb259c18ae8b55518, 0x15551f6531b2cb72, 0x566ff258d900762a, 0x18a94bd29c1e1cf0,
-    0x2bf36dd218146064, 0xcf273f5486d8f0e8, 0xa2d7fd1ed5148192, 0x8930570c4c7fa5f4,
-    0xc50bf673f309cb06, 0xef351bee5aec33a6, 0xe5af351bd1abba3c, 0xa206e6a9accd09c4,
-    0x00990549ccd151ca, 0x63a814ecd16089cc, 0xae0af0a717a05822, 0xb68a8620f18be904,
-    0x2ee24376fed4a35a, 0xe7ab997a69dff1ba, 0xc86f40fa6adc2f9a, 0x8f64f0408792ac4e,
-    0x3f64a2827c83a934, 0x99ae16c0ca4a27a6, 0x392b663d14369364, 0x95ce7bfa37969836,
-    0x69b3066363eb6e1e, 0xf09c73e44671b25e, 0x30c27a940c9be840, 0xe3b1b5c4be179d7c,
-    0x67eef82b5d0abdf8, 0x7911677225d62138, 0x2ad45d92d75fdd4a, 0x35400b6bc15a1d0e,
-    0xaa01ae0a4f89771c, 0xc6d8ae32c8439888, 0x2789a50d986ddc72, 0xaca9447b03165502,
-    0xef63b827a2c357b8, 0xe69e89bcbf1abd6a, 0xc0e2fc2e94d91344, 0xa8fb2c924cd4423c,
-    0xb6274864576d3d20, 0xeecd2c13f16bf878, 0x43cd58ab7db9b592, 0x36ad6c56c22cdbd4,
-    0xe91ecd7272f2fd38, 0x6be665f381cd5d34, 0x98e67ed5350f1b60, 0x7b42c3c839821184,
-    0x6fae95ca6b229aa2, 0x9a92761623a6c8d2, 0x9c4c9a3bf752e834, 0x53a3e5b8e86db80c,
-    0xe0e7002cc098544e, 0x463a6dd2dd27e7aa, 0xeccd10232f071a32, 0x945506121555a818,
-    0xe3cec2b22cd166ba, 0xe6c646c92fee614e, 0x602101c6e6f3ba9a, 0xa05bd452e304e084,
-    0x858bd70b1e64c4be, 0xf0d5f73dbf5f7bfe, 0xb5dc1b0d09216548, 0xc2e6cd664d0c13ec,
-    0x5c1c6b41fc8c2e7c, 0xa340fbd27d049e22, 0x0f371622bd499950, 0x275324e8ab1f5d76,
-    0xf63cdc45c1140766, 0xd4c6bfb746d31ba0, 0x9ea6cb2650a074b8, 0x9bc7663cdfabaf00,
-    0x1c7c8443a6c28826, 0xde29a1b0d7e34458, 0xc3b061a7e2d8bbb6, 0x557a56548a2a09c2
-];
+#[cfg(test)]
+mod tests {
+    use crate::fastcdc::Normalization;
+    use crate::supercdc::{
+        load_records, masks, save_records, BoundedRecords, Chunker, Eviction, RecordStore,
+    };
+    use crate::{generate_data, Chunk, SizeParams};
+    use std::collections::HashMap;
+    use std::io;
+
+    #[test]
+    fn masks_follow_average_size() {
+        // 8KB, 4KB and 2KB masks
+        assert_eq!(
+            masks(Chunker::default_sizes(), Normalization::Level1).unwrap(),
+            (0b1111_1111_1111, 0b111_1111_1111, 0b11_1111_1111)
+        );
+        assert!(masks(SizeParams::new(16, 32, 64), Normalization::Level3).is_err());
+
+        let data = generate_data(4 * 1024 * 1024);
+        let lengths = |avg: usize, normalization| {
+            let sizes = SizeParams::new(avg / 2, avg, avg * 8);
+            Chunker::try_with_params(&data, HashMap::new(), sizes, normalization, None)
+                .unwrap()
+                .map(|chunk| chunk.len as f64)
+                .collect::<Vec<_>>()
+        };
+        let average = |lengths: &[f64]| lengths.iter().sum::<f64>() / lengths.len() as f64;
+        let deviation = |lengths: &[f64]| {
+            let average = average(lengths);
+            let variance = lengths
+                .iter()
+                .map(|len| (len - average).powi(2))
+                .sum::<f64>();
+            (variance / lengths.len() as f64).sqrt()
+        };
+
+        let small = average(&lengths(8192, Normalization::Level1));
+        let large = average(&lengths(65536, Normalization::Level1));
+        assert!(
+            large / small > 6.0 && large / small < 10.0,
+            "{} {}",
+            small,
+            large
+        );
+
+        let loose = deviation(&lengths(16384, Normalization::Level0));
+        let tight = deviation(&lengths(16384, Normalization::Level3));
+        assert!(tight < loose / 2.0, "{} {}", tight, loose);
+    }
+
+    #[test]
+    fn bounded_records_evict_by_policy() {
+        let mut lru = BoundedRecords::new(2, Eviction::LeastRecentlyUsed);
+        let mut lfu = BoundedRecords::new(2, Eviction::LeastFrequentlyUsed);
+        for records in [&mut lru, &mut lfu] {
+            records.insert(1, 10);
+            records.get(1);
+            records.insert(2, 20);
+            records.insert(3, 30);
+        }
+
+        assert_eq!(lru.records(), vec![(2, 20), (3, 30)]);
+        assert_eq!(lfu.records(), vec![(3, 30), (1, 10)]);
+    }
+
+    #[test]
+    fn saved_records_are_reused() {
+        let data = generate_data(4 * 1024 * 1024);
+        let sizes = Chunker::default_sizes();
+        let records = BoundedRecords::new(100, Eviction::LeastRecentlyUsed);
+
+        let mut chunker = Chunker::with_key(&data, records, sizes, None);
+        chunker.by_ref().for_each(drop);
+        let records = chunker.give_records();
+        assert_eq!(records.len(), 100);
+
+        let mut file = vec![];
+        save_records(&records, &mut file).unwrap();
+        assert_eq!(file.len(), 12 + 100 * 12);
+
+        let mut loaded = HashMap::new();
+        load_records(file.as_slice(), sizes, &mut loaded).unwrap();
+        let mut expected = records.records();
+        let mut actual = loaded.records();
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+
+        let mut reloaded = BoundedRecords::new(100, Eviction::LeastRecentlyUsed);
+        load_records(file.as_slice(), sizes, &mut reloaded).unwrap();
+        assert_eq!(reloaded.records(), records.records());
+        let remembered = Chunker::with_key(&data, records, sizes, None).collect::<Vec<_>>();
+        let reloaded = Chunker::with_key(&data, reloaded, sizes, None).collect::<Vec<_>>();
+        assert_eq!(reloaded, remembered);
+
+        assert!(load_records(&file[1..], sizes, &mut HashMap::new()).is_err());
+        assert!(load_records(&file[..file.len() - 1], sizes, &mut HashMap::new()).is_err());
+
+        // lengths are checked against the sizes the records are loaded for
+        let longest = expected.iter().map(|&(_, length)| length).max().unwrap();
+        let smaller = SizeParams::new(sizes.min, sizes.avg.min(longest - 1), longest - 1);
+        let error = load_records(file.as_slice(), smaller, &mut HashMap::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn records_that_do_not_fit_sizes_are_ignored() {
+        let data = generate_data(1024 * 1024);
+        let sizes = Chunker::default_sizes();
+
+        let mut chunker = Chunker::with_records(&data, HashMap::new(), sizes);
+        chunker.by_ref().for_each(drop);
+        let records = chunker.give_records();
+
+        for length in [0, sizes.min - 1, sizes.max + 1] {
+            let broken = records.keys().map(|&hash| (hash, length)).collect();
+            let chunks = Chunker::with_records(&data, broken, sizes).collect::<Vec<_>>();
+
+            let (last, chunks) = chunks.split_last().unwrap();
+            assert_eq!(last.pos + last.len, data.len());
+            assert!(chunks
+                .iter()
+                .all(|chunk| (sizes.min..=sizes.max).contains(&chunk.len)));
+        }
+    }
+
+    #[test]
+    fn chunks_without_records_do_not_depend_on_history() {
+        let (history, data) = (generate_data(1024 * 1024), generate_data(1024 * 1024));
+        let sizes = Chunker::default_sizes();
+
+        let mut chunker = Chunker::with_records(&history, HashMap::new(), sizes);
+        chunker.by_ref().for_each(drop);
+        let records = chunker.give_records();
+
+        let fresh = Chunker::with_records(&data, HashMap::new(), sizes).collect::<Vec<_>>();
+        let learned = Chunker::with_records(&data, records, sizes).collect::<Vec<_>>();
+        assert_ne!(fresh, learned);
+
+        // every border is found by looking only at the data of its chunk
+        let chunks = Chunker::without_records(&data, sizes).collect::<Vec<_>>();
+        for chunk in &chunks {
+            let rest = &data[chunk.pos..];
+            let first = Chunker::without_records(rest, sizes).next();
+            assert_eq!(first, Some(Chunk::new(0, chunk.len)));
+        }
+    }
+}
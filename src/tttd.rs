@@ -0,0 +1,258 @@
+use crate::rabin::{ChunkerParams, WIN_SLIDE_OFFSET};
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
+use std::cmp::min;
+
+// thresholds and divisors recommended in the TTTD paper
+const MIN_CHUNK_SIZE: usize = 460;
+const AVG_CHUNK_SIZE: usize = 1000;
+const MAX_CHUNK_SIZE: usize = 2800;
+
+/// Two Thresholds, Two Divisors chunker using Rabin fingerprints.
+///
+/// A border is placed where the fingerprint modulo the main divisor equals `divisor - 1`.
+/// If no such border is found before max size, the last border found using
+/// the smaller backup divisor is used instead, and max size only if there was none.
+pub struct Chunker<'a> {
+    buf: &'a [u8],
+    params: ChunkerParams,
+    pos: usize,
+    sizes: SizeParams,
+    main_divisor: u64,
+    backup_divisor: u64,
+}
+
+impl<'a> Chunker<'a> {
+    pub fn default_sizes() -> SizeParams {
+        SizeParams {
+            min: MIN_CHUNK_SIZE,
+            avg: AVG_CHUNK_SIZE,
+            max: MAX_CHUNK_SIZE,
+        }
+    }
+
+    /// Creates a new chunker with the main divisor of `avg - min`
+    /// and the backup divisor of half of it, as recommended in the paper.
+    pub fn new(buf: &'a [u8], params: ChunkerParams, sizes: SizeParams) -> Self {
+        let main_divisor = (sizes.avg - sizes.min) as u64;
+        Self::with_divisors(buf, params, sizes, main_divisor, (main_divisor / 2).max(1))
+    }
+
+    /// Creates a new chunker with the given divisors, e.g. ones an existing archive was chunked with.
+    pub fn with_divisors(
+        buf: &'a [u8],
+        params: ChunkerParams,
+        sizes: SizeParams,
+        main_divisor: u64,
+        backup_divisor: u64,
+    ) -> Self {
+        Self {
+            buf,
+            params,
+            pos: 0,
+            sizes,
+            main_divisor,
+            backup_divisor,
+        }
+    }
+
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(
+        buf: &'a [u8],
+        params: ChunkerParams,
+        sizes: SizeParams,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &params)?;
+        Ok(Self::new(buf, params, sizes))
+    }
+
+    /// Creates a new chunker if `sizes` and divisors can be used by the algorithm.
+    pub fn try_with_divisors(
+        buf: &'a [u8],
+        params: ChunkerParams,
+        sizes: SizeParams,
+        main_divisor: u64,
+        backup_divisor: u64,
+    ) -> Result<Self, ChunkerError> {
+        sizes.check_with_min(0)?;
+        if main_divisor == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "main_divisor",
+                reason: "has to be positive",
+            });
+        }
+        if backup_divisor == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "backup_divisor",
+                reason: "has to be positive",
+            });
+        }
+
+        Ok(Self::with_divisors(
+            buf,
+            params,
+            sizes,
+            main_divisor,
+            backup_divisor,
+        ))
+    }
+
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            params: self.params,
+            pos: 0,
+            sizes: self.sizes,
+            main_divisor: self.main_divisor,
+            backup_divisor: self.backup_divisor,
+        }
+    }
+
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max
+    }
+
+    fn find_border(&self, buf: &[u8]) -> usize {
+        if buf.len() <= self.sizes.min {
+            return buf.len();
+        }
+
+        let remaining = min(self.sizes.max, buf.len());
+
+        let mut fingerprint = self.params.fingerprint();
+        let mut backup_border = None;
+        let mut chunk_len = self.sizes.min.saturating_sub(WIN_SLIDE_OFFSET);
+
+        while chunk_len < remaining {
+            let checksum = fingerprint.slide(buf[chunk_len]);
+            chunk_len += 1;
+
+            if chunk_len < self.sizes.min {
+                continue;
+            }
+
+            if checksum % self.backup_divisor == self.backup_divisor - 1 {
+                backup_border = Some(chunk_len);
+            }
+            if checksum % self.main_divisor == self.main_divisor - 1 {
+                return chunk_len;
+            }
+        }
+
+        if chunk_len == self.sizes.max {
+            backup_border.unwrap_or(chunk_len)
+        } else {
+            chunk_len
+        }
+    }
+
+    pub fn give_params(self) -> ChunkerParams {
+        self.params
+    }
+}
+
+impl Iterator for Chunker<'_> {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            return None;
+        }
+
+        let length = self.find_border(&self.buf[self.pos..]);
+        let chunk = Chunk::new(self.pos, length);
+        self.pos += length;
+
+        Some(chunk)
+    }
+}
+
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = ChunkerParams;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        ChunkerParams::new()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::new(buf, config, sizes)
+    }
+
+    fn validate(sizes: SizeParams, _config: &Self::Config) -> Result<(), ChunkerError> {
+        // window starts sliding at most WIN_SLIDE_OFFSET bytes before min size,
+        // and the main divisor is avg - min
+        sizes.check_with_min(0)?;
+
+        if sizes.avg == sizes.min {
+            return Err(ChunkerError::InvalidSizes {
+                sizes,
+                reason: "avg has to be greater than min",
+            });
+        }
+        Ok(())
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rabin::ChunkerParams;
+    use crate::tttd::Chunker;
+    use crate::{generate_data, SizeParams};
+
+    #[test]
+    fn average_size_follows_sizes() {
+        let data = generate_data(4 * 1024 * 1024);
+
+        for sizes in [Chunker::default_sizes(), SizeParams::new(2048, 6144, 32768)] {
+            let chunks = Chunker::new(&data, ChunkerParams::new(), sizes).collect::<Vec<_>>();
+            let average = data.len() / chunks.len();
+            assert!(
+                average.abs_diff(sizes.avg) < sizes.avg / 10,
+                "{} {}",
+                sizes,
+                average
+            );
+        }
+    }
+
+    #[test]
+    fn backup_border_is_used_at_max_size() {
+        let data = generate_data(1024 * 1024);
+        let sizes = SizeParams::new(1024, 2048, 8192);
+        let lengths = |backup_divisor| {
+            Chunker::with_divisors(&data, ChunkerParams::new(), sizes, u64::MAX, backup_divisor)
+                .map(|chunk| chunk.len)
+                .collect::<Vec<_>>()
+        };
+
+        // main divisor never matches, so chunks are cut at backup borders or max size
+        let with_backup = lengths(1024);
+        let (_, rest) = with_backup.split_last().unwrap();
+        assert!(rest.iter().all(|&len| len >= sizes.min && len <= sizes.max));
+        assert!(rest.iter().filter(|&&len| len < sizes.max).count() > rest.len() / 2);
+
+        let without_backup = lengths(u64::MAX);
+        let (last, rest) = without_backup.split_last().unwrap();
+        assert!(rest.iter().all(|&len| len == sizes.max));
+        assert!(*last <= sizes.max);
+    }
+
+    #[test]
+    fn invalid_divisors_are_rejected() {
+        let sizes = SizeParams::new(1024, 2048, 8192);
+        let params = ChunkerParams::new();
+        assert!(Chunker::try_with_divisors(&[], params.clone(), sizes, 540, 270).is_ok());
+        assert!(Chunker::try_with_divisors(&[], params.clone(), sizes, 0, 270).is_err());
+        assert!(Chunker::try_with_divisors(&[], params.clone(), sizes, 540, 0).is_err());
+        assert!(Chunker::try_new(&[], params, SizeParams::new(1024, 1024, 8192)).is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use crate::{Chunk, SizeParams};
+use crate::rng::KeyStream;
+use crate::{Chunk, ChunkerError, Chunking, SizeParams};
 
 const KB: usize = 1024;
 const MIN_CHUNK_SIZE: usize = 2 * KB;
@@ -12,17 +13,118 @@ const MASK_L: usize = 0x2C;
 
 const LEST: usize = 64;
 
+// distance of a window is the sum of bit distances of its bytes, so windows are limited
+// to keep probabilities of distances representable
+const MAX_WINDOW_SIZE: usize = 32;
+
+/// Contains parameters specified in the UltraCDC paper.
+///
+/// Masks are derived from sizes unless they are given explicitly.
+/// Bytes are assigned distances in the order derived from a secret key if it is given.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    window_size: usize,
+    lest: usize,
+    masks: Option<(usize, usize)>,
+    key: Option<[u8; 32]>,
+}
+
+impl Config {
+    pub fn new(window_size: usize, lest: usize) -> Self {
+        Self {
+            window_size,
+            lest,
+            masks: None,
+            key: None,
+        }
+    }
+
+    /// Returns the config that assigns distances to bytes in the order derived from a secret `key`.
+    pub fn with_key(self, key: [u8; 32]) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    pub fn key(&self) -> Option<&[u8; 32]> {
+        self.key.as_ref()
+    }
+
+    /// Returns the config that uses the given masks before and after the normal size.
+    pub fn with_masks(self, mask_s: usize, mask_l: usize) -> Self {
+        Self {
+            masks: Some((mask_s, mask_l)),
+            ..self
+        }
+    }
+
+    /// Returns masks used before and after the normal size for the given sizes.
+    ///
+    /// Derived masks keep the expected cut lengths of the masks from the paper
+    /// relative to `avg - min`, so that default sizes get the paper's masks.
+    pub fn masks(&self, sizes: SizeParams) -> (usize, usize) {
+        if let Some(masks) = self.masks {
+            return masks;
+        }
+
+        let paper_length = (NORMAL_CHUNK_SIZE - MIN_CHUNK_SIZE) as f64;
+        let target = sizes.avg.saturating_sub(sizes.min).max(1) as f64 / paper_length;
+
+        let closest_mask = |paper_mask| {
+            let length = target * expected_cut_length(paper_mask, WINDOW_SIZE);
+            let bits = (self.window_size * 8 + 1).next_power_of_two();
+            (1..bits)
+                .map(|mask| (mask, expected_cut_length(mask, self.window_size)))
+                .filter(|(_, expected)| expected.is_finite() && *expected > 1.0)
+                .min_by(|(_, x), (_, y)| {
+                    (x.ln() - length.ln())
+                        .abs()
+                        .total_cmp(&(y.ln() - length.ln()).abs())
+                })
+                .map_or(paper_mask, |(mask, _)| mask)
+        };
+
+        (closest_mask(MASK_S), closest_mask(MASK_L))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(WINDOW_SIZE, LEST)
+    }
+}
+
+/// Expected amount of checks before the distance of a random window matches `mask`,
+/// where the distance is binomially distributed.
+fn expected_cut_length(mask: usize, window_size: usize) -> f64 {
+    let bits = window_size * 8;
+    let mut probability = 0.5f64.powi(bits as i32);
+    let mut matching = 0.0;
+    for distance in 0..=bits {
+        if distance & mask == 0 {
+            matching += probability;
+        }
+        probability = probability * (bits - distance) as f64 / (distance + 1) as f64;
+    }
+    1.0 / matching
+}
+
 pub struct Chunker<'a> {
     buf: &'a [u8],
     buf_len: usize,
-    out_window: [u8; WINDOW_SIZE],
-    in_window: [u8; WINDOW_SIZE],
-    normal_size: usize,
+    out_window: Vec<u8>,
+    in_window: Vec<u8>,
+    window_size: usize,
+    mask_s: usize,
+    mask_l: usize,
+    lest: usize,
     start: usize,
     chk_len: usize,
     distance: usize,
     equal_window_count: usize,
     sizes: SizeParams,
+    distances: Box<[usize; 256]>,
 }
 
 impl<'a> Chunker<'a> {
@@ -35,48 +137,86 @@ impl<'a> Chunker<'a> {
     }
 
     pub fn new(buf: &'a [u8], sizes: SizeParams) -> Self {
+        Self::with_key(buf, sizes, None)
+    }
+
+    /// Creates a new chunker that assigns distances to bytes in the order
+    /// derived from a secret `key` if it is given.
+    pub fn with_key(buf: &'a [u8], sizes: SizeParams, key: Option<&[u8; 32]>) -> Self {
+        let config = match key {
+            Some(&key) => Config::default().with_key(key),
+            None => Config::default(),
+        };
+        Self::with_params(buf, sizes, config)
+    }
+
+    /// Creates a new chunker with the given parameters.
+    pub fn with_params(buf: &'a [u8], sizes: SizeParams, config: Config) -> Self {
+        let (mask_s, mask_l) = config.masks(sizes);
         Self {
             buf,
             buf_len: buf.len(),
-            out_window: [0u8; WINDOW_SIZE],
-            in_window: [0u8; WINDOW_SIZE],
-            normal_size: sizes.avg,
+            out_window: vec![0u8; config.window_size],
+            in_window: vec![0u8; config.window_size],
+            window_size: config.window_size,
+            mask_s,
+            mask_l,
+            lest: config.lest,
             start: 0,
             chk_len: sizes.min,
             distance: 0,
             equal_window_count: 0,
             sizes,
+            distances: byte_distances(config.key()),
         }
     }
 
-    pub fn generate_chunks(&mut self) -> Vec<Chunk> {
-        let mut chunks: Vec<Chunk> = vec![];
-        self.normal_size = self.sizes.avg;
-        if self.buf_len <= self.sizes.min {
-            return vec![Chunk::new(0, self.buf_len)];
-        }
-
-        if self.buf_len <= self.normal_size {
-            self.normal_size = self.buf_len;
-        }
-
-        while self.start + self.chk_len < self.buf_len {
-            let chunk = self.generate_chunk();
+    /// Creates a new chunker if `sizes` can be used by the algorithm.
+    pub fn try_new(buf: &'a [u8], sizes: SizeParams) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &Self::default_config())?;
+        Ok(Self::new(buf, sizes))
+    }
 
-            if !chunks.is_empty() {
-                let last = chunks.len() - 1;
-                assert_eq!(chunks[last].pos + chunks[last].len, chunk.pos);
-            }
+    /// Creates a new chunker if `sizes` and `config` can be used by the algorithm.
+    pub fn try_with_params(
+        buf: &'a [u8],
+        sizes: SizeParams,
+        config: Config,
+    ) -> Result<Self, ChunkerError> {
+        Self::validate(sizes, &config)?;
+        Ok(Self::with_params(buf, sizes, config))
+    }
 
-            chunks.push(chunk);
+    /// Moves the chunker onto `buf`, which has to start at the beginning of the next chunk.
+    pub(crate) fn rebase(self, buf: &[u8]) -> Chunker<'_> {
+        Chunker {
+            buf,
+            buf_len: buf.len(),
+            out_window: self.out_window,
+            in_window: self.in_window,
+            window_size: self.window_size,
+            mask_s: self.mask_s,
+            mask_l: self.mask_l,
+            lest: self.lest,
+            start: 0,
+            chk_len: self.chk_len,
+            distance: self.distance,
+            equal_window_count: self.equal_window_count,
+            sizes: self.sizes,
+            distances: self.distances,
         }
+    }
 
-        if self.start + self.chk_len >= self.buf_len && self.start != self.buf_len {
-            self.chk_len = self.buf_len - self.start;
-            chunks.push(Chunk::new(self.start, self.chk_len));
-        }
+    /// Amount of bytes after the chunk start that is enough to find the next border.
+    pub(crate) fn lookahead(&self) -> usize {
+        self.sizes.max + 2 * self.window_size + 1
+    }
 
-        chunks
+    /// Returns all remaining chunks of the buffer, same as the ones given out by the iterator.
+    ///
+    /// An empty buffer has no chunks.
+    pub fn generate_chunks(&mut self) -> Vec<Chunk> {
+        self.by_ref().collect()
     }
 
     fn generate_chunk(&mut self) -> Chunk {
@@ -85,15 +225,15 @@ impl<'a> Chunker<'a> {
         }
 
         self.out_window
-            .copy_from_slice(&self.buf[self.start..self.start + 8]);
-        self.chk_len += 8;
+            .copy_from_slice(&self.buf[self.start..self.start + self.window_size]);
+        self.chk_len += self.window_size;
         self.calculate_new_distance();
 
-        if let Some(chunk) = self.try_get_chunk(self.normal_size, MASK_S) {
+        if let Some(chunk) = self.try_get_chunk(self.sizes.avg, self.mask_s) {
             return chunk;
         }
 
-        if let Some(chunk) = self.try_get_chunk(self.sizes.max, MASK_L) {
+        if let Some(chunk) = self.try_get_chunk(self.sizes.max, self.mask_l) {
             return chunk;
         }
 
@@ -107,15 +247,15 @@ impl<'a> Chunker<'a> {
             }
 
             self.in_window.copy_from_slice(
-                &self.buf[self.start + self.chk_len..self.start + self.chk_len + 8],
+                &self.buf[self.start + self.chk_len..self.start + self.chk_len + self.window_size],
             );
 
             if self.in_window == self.out_window {
                 self.equal_window_count += 1;
-                if self.equal_window_count == LEST {
-                    return Some(self.make_chunk(8));
+                if self.equal_window_count == self.lest {
+                    return Some(self.make_chunk(self.window_size));
                 } else {
-                    self.chk_len += 8;
+                    self.chk_len += self.window_size;
                     continue;
                 }
             }
@@ -126,15 +266,15 @@ impl<'a> Chunker<'a> {
             }
 
             self.out_window.copy_from_slice(&self.in_window);
-            self.chk_len += 8;
+            self.chk_len += self.window_size;
         }
         None
     }
 
     fn try_extract(&mut self, mask: usize) -> Option<Chunk> {
-        for j in 0..WINDOW_SIZE {
+        for j in 0..self.window_size {
             if (self.distance & mask) == 0 {
-                return Some(self.make_chunk(8));
+                return Some(self.make_chunk(self.window_size));
             }
 
             // self.distance = (self.distance << 1) + DISTANCE_MAP[BYTE][self.in_window[j] as usize];
@@ -147,7 +287,7 @@ impl<'a> Chunker<'a> {
         self.distance = self
             .out_window
             .iter()
-            .map(|&byte| BYTE_DISTANCES[byte as usize])
+            .map(|&byte| self.distances[byte as usize])
             .sum();
     }
 
@@ -155,8 +295,8 @@ impl<'a> Chunker<'a> {
         let old = self.out_window[index];
         let new = self.in_window[index];
 
-        self.distance += BYTE_DISTANCES[new as usize];
-        self.distance -= BYTE_DISTANCES[old as usize];
+        self.distance += self.distances[new as usize];
+        self.distance -= self.distances[old as usize];
     }
 
     fn make_chunk(&mut self, add_len: usize) -> Chunk {
@@ -172,7 +312,7 @@ impl<'a> Chunker<'a> {
     }
 
     fn check_border(&mut self) -> Option<Chunk> {
-        if self.start + self.chk_len + 8 >= self.buf_len {
+        if self.start + self.chk_len + self.window_size >= self.buf_len {
             let pos = self.start;
             let len = self.buf_len - self.start;
 
@@ -197,6 +337,60 @@ impl Iterator for Chunker<'_> {
     }
 }
 
+impl<'a> Chunking<'a> for Chunker<'a> {
+    type Config = Config;
+
+    fn default_sizes() -> SizeParams {
+        Chunker::default_sizes()
+    }
+
+    fn default_config() -> Self::Config {
+        Config::default()
+    }
+
+    fn with_config(buf: &'a [u8], sizes: SizeParams, config: Self::Config) -> Self {
+        Chunker::with_params(buf, sizes, config)
+    }
+
+    fn validate(sizes: SizeParams, config: &Self::Config) -> Result<(), ChunkerError> {
+        if config.window_size == 0 || config.window_size > MAX_WINDOW_SIZE {
+            return Err(ChunkerError::InvalidParameter {
+                name: "window_size",
+                reason: "has to be between 1 and 32",
+            });
+        }
+        if config.lest == 0 {
+            return Err(ChunkerError::InvalidParameter {
+                name: "lest",
+                reason: "has to be positive",
+            });
+        }
+        if matches!(config.masks, Some((0, _) | (_, 0))) {
+            return Err(ChunkerError::InvalidParameter {
+                name: "masks",
+                reason: "have to be positive",
+            });
+        }
+
+        sizes.check_with_min(config.window_size)
+    }
+
+    fn sizes(&self) -> SizeParams {
+        self.sizes
+    }
+}
+
+/// Returns `BYTE_DISTANCES` shuffled using SHAKE256 output for `key`, or unchanged if there is no key.
+fn byte_distances(key: Option<&[u8; 32]>) -> Box<[usize; 256]> {
+    match key {
+        Some(key) => {
+            let permutation = KeyStream::new(b"ultra distances", key).permutation();
+            Box::new(permutation.map(|byte| BYTE_DISTANCES[byte as usize]))
+        }
+        None => Box::new(BYTE_DISTANCES),
+    }
+}
+
 const BYTE_DISTANCES: [usize; 256] = [
     4, 5, 3, 4, 5, 6, 4, 5, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6, 4, 5, 6, 7, 5, 6, 4, 5, 3, 4, 5, 6, 4, 5,
     3, 4, 2, 3, 4, 5, 3, 4, 2, 3, 1, 2, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6, 4, 5, 3, 4, 2, 3, 4, 5, 3, 4,
@@ -207,3 +401,136 @@ const BYTE_DISTANCES: [usize; 256] = [
     4, 5, 3, 4, 5, 6, 4, 5, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6, 4, 5, 6, 7, 5, 6, 4, 5, 3, 4, 5, 6, 4, 5,
     3, 4, 2, 3, 4, 5, 3, 4, 2, 3, 1, 2, 3, 4, 2, 3, 4, 5, 3, 4, 5, 6, 4, 5, 3, 4, 2, 3, 4, 5, 3, 4,
 ];
+
+#[cfg(test)]
+mod tests {
+    use crate::ultra::{byte_distances, Chunker, Config, LEST, MASK_L, MASK_S, WINDOW_SIZE};
+    use crate::{generate_data, Chunk, Chunking, SizeParams};
+
+    /// Finds chunk borders of `buf` by indexing windows directly, as a reference for the chunker.
+    ///
+    /// Windows that are equal to the previous one are counted across chunks.
+    fn reference_chunks(buf: &[u8], sizes: SizeParams) -> Vec<Chunk> {
+        let (mask_s, mask_l) = Config::default().masks(sizes);
+        let distances = byte_distances(None);
+        let window = WINDOW_SIZE;
+
+        let mut chunks = vec![];
+        let mut equal_windows = 0;
+        let mut start = 0;
+        while start < buf.len() {
+            let rest = &buf[start..];
+            let border = 'border: {
+                let mut len = sizes.min;
+                if len + window >= rest.len() {
+                    break 'border rest.len();
+                }
+
+                // first window of the chunk is compared with the window after min size
+                let mut out_window = &rest[..window];
+                let mut distance = out_window
+                    .iter()
+                    .map(|&byte| distances[byte as usize])
+                    .sum::<usize>();
+                len += window;
+
+                for (limit, mask) in [(sizes.avg, mask_s), (sizes.max, mask_l)] {
+                    while len < limit {
+                        if len + window >= rest.len() {
+                            break 'border rest.len();
+                        }
+
+                        let in_window = &rest[len..len + window];
+                        if in_window == out_window {
+                            equal_windows += 1;
+                            if equal_windows == LEST {
+                                break 'border len + window;
+                            }
+                            len += window;
+                            continue;
+                        }
+
+                        equal_windows = 0;
+                        for (&old, &new) in out_window.iter().zip(in_window) {
+                            if distance & mask == 0 {
+                                break 'border len + window;
+                            }
+                            distance = distance + distances[new as usize] - distances[old as usize];
+                        }
+                        out_window = in_window;
+                        len += window;
+                    }
+                }
+                len
+            };
+            chunks.push(Chunk::new(start, border));
+            start += border;
+        }
+        chunks
+    }
+
+    #[test]
+    fn masks_follow_average_size() {
+        assert_eq!(
+            Config::default().masks(Chunker::default_sizes()),
+            (MASK_S, MASK_L)
+        );
+        let masks = Config::default().with_masks(1, 2);
+        assert_eq!(masks.masks(Chunker::default_sizes()), (1, 2));
+
+        let data = generate_data(4 * 1024 * 1024);
+
+        // average sizes evaluated in the paper
+        let mut previous = 0;
+        for avg in [4096, 16384, 65536] {
+            let sizes = SizeParams::new(avg / 4, avg, avg * 8);
+            let chunks = Chunker::try_with_config(&data, sizes, Chunker::default_config())
+                .unwrap()
+                .count();
+
+            let average = data.len() / chunks;
+            assert!(
+                average > avg * 3 / 4 && average < avg * 3 / 2,
+                "{} {}",
+                sizes,
+                average
+            );
+            assert!(average > previous * 2);
+            previous = average;
+        }
+    }
+
+    #[test]
+    fn generated_chunks_match_iterator_around_edge_sizes() {
+        let sizes = Chunker::default_sizes();
+        let window = 8;
+        let random = generate_data(sizes.max * 3);
+        let repeated = vec![7u8; sizes.max * 3];
+
+        let mut lengths = vec![];
+        for edge in [0, sizes.min, sizes.avg, sizes.max, sizes.min + sizes.max] {
+            let from = edge.saturating_sub(2 * window + 1);
+            lengths.extend(from..=edge + 2 * window + 1);
+        }
+
+        for data in [&random, &repeated] {
+            for &len in &lengths {
+                let buf = &data[..len];
+                let generated = Chunker::new(buf, sizes).generate_chunks();
+                let iterated = Chunker::new(buf, sizes).collect::<Vec<Chunk>>();
+                assert_eq!(generated, iterated, "length {}", len);
+                assert_eq!(generated, reference_chunks(buf, sizes), "length {}", len);
+
+                let mut end = 0;
+                for chunk in &generated {
+                    assert_eq!(chunk.pos, end, "length {}", len);
+                    assert!(chunk.len > 0 && chunk.len <= sizes.max + window);
+                    end += chunk.len;
+                }
+                assert_eq!(end, len);
+            }
+        }
+
+        assert_eq!(Chunker::new(&[], sizes).generate_chunks(), vec![]);
+    }
+}